@@ -1,11 +1,19 @@
 #![deny(clippy::all)]
 #![deny(clippy::pedantic)]
 
+use kafka_bridge::checkpoint::{self, FileStore, KafkaStore, Store};
+use kafka_bridge::crypto::{Cryptor, Mode};
+use kafka_bridge::http::Limits;
 use kafka_bridge::kafka;
 #[cfg(any(feature = "sasl-plain", feature = "sasl-ssl"))]
 use kafka_bridge::kafka::SASLConfig;
-use kafka_bridge::pubnub;
-use std::{env, process, thread, time};
+use kafka_bridge::pubnub::{self, AuthKey, PublishOptions};
+use kafka_bridge::redact;
+use kafka_bridge::socket::{self, ReconnectPolicy, TlsConfig};
+use kafka_bridge::timetoken::Timetoken;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::{env, process};
 use tokio::sync::mpsc;
 use tokio::time::{delay_for, Duration};
 
@@ -17,11 +25,27 @@ struct Configuration {
     pub kafka_topic: String,
     pub kafka_group: String,
     pub pubnub_host: String,
+    pub pubnub_tls: TlsConfig,
+    pub pubnub_reconnect: ReconnectPolicy,
+    pub pubnub_limits: Limits,
     pub pubnub_channel: String,
     pub pubnub_channel_root: String,
+    pub pubnub_channel_groups: Vec<String>,
     pub publish_key: String,
     pub subscribe_key: String,
     pub secret_key: String,
+    pub auth_key: Option<AuthKey>,
+    pub cryptor: Option<Cryptor>,
+    pub filter_expression: String,
+    pub publish_meta: String,
+    pub publish_options: HashMap<String, PublishOptions>,
+    pub checkpoint_file: String,
+    pub checkpoint_topic: String,
+    pub backfill: bool,
+    pub presence_topic: String,
+    pub presence_table_topic: String,
+    pub heartbeat: u32,
+    pub log_payloads: bool,
     #[cfg(any(feature = "sasl-plain", feature = "sasl-ssl"))]
     pub sasl_cfg: SASLConfig,
 }
@@ -34,12 +58,71 @@ fn environment_variables() -> Configuration {
             .collect(),
         kafka_topic: fetch_env_var("KAFKA_TOPIC"),
         kafka_group: fetch_env_var("KAFKA_GROUP"),
-        pubnub_host: "psdsn.pubnub.com:80".into(),
+        pubnub_host: fetch_env_var_or(
+            "PUBNUB_HOST",
+            "https://psdsn.pubnub.com",
+        ),
+        pubnub_tls: TlsConfig {
+            ca_location: fetch_env_var_or("PUBNUB_TLS_CA_LOCATION", ""),
+            server_name: fetch_env_var_or("PUBNUB_TLS_SERVER_NAME", ""),
+        },
+        pubnub_reconnect: ReconnectPolicy {
+            initial_delay: Duration::from_millis(fetch_env_number_or(
+                "PUBNUB_RECONNECT_INITIAL_DELAY_MS",
+                1000,
+            )),
+            max_delay: Duration::from_millis(fetch_env_number_or(
+                "PUBNUB_RECONNECT_MAX_DELAY_MS",
+                30_000,
+            )),
+            max_attempts: fetch_env_number_or(
+                "PUBNUB_RECONNECT_MAX_ATTEMPTS",
+                10,
+            ),
+            ..ReconnectPolicy::default()
+        },
+        pubnub_limits: Limits {
+            max_header_bytes: fetch_env_number_or(
+                "PUBNUB_MAX_HEADER_BYTES",
+                Limits::default().max_header_bytes,
+            ),
+            max_headers: fetch_env_number_or(
+                "PUBNUB_MAX_HEADERS",
+                Limits::default().max_headers,
+            ),
+            max_body_bytes: fetch_env_number_or(
+                "PUBNUB_MAX_BODY_BYTES",
+                Limits::default().max_body_bytes,
+            ),
+        },
         pubnub_channel: fetch_env_var("PUBNUB_CHANNEL"),
         pubnub_channel_root: fetch_env_var("PUBNUB_CHANNEL_ROOT"),
+        pubnub_channel_groups: fetch_env_var_or("PUBNUB_CHANNEL_GROUPS", "")
+            .split(',')
+            .filter(|group| !group.is_empty())
+            .map(std::string::ToString::to_string)
+            .collect(),
         publish_key: fetch_env_var("PUBNUB_PUBLISH_KEY"),
         subscribe_key: fetch_env_var("PUBNUB_SUBSCRIBE_KEY"),
         secret_key: fetch_env_var("PUBNUB_SECRET_KEY"),
+        auth_key: fetch_auth_key(),
+        cryptor: fetch_cryptor(),
+        filter_expression: fetch_env_var_or("PUBNUB_FILTER_EXPRESSION", ""),
+        publish_meta: fetch_env_var_or("PUBNUB_PUBLISH_META", ""),
+        publish_options: fetch_publish_options(),
+        checkpoint_file: fetch_env_var_or(
+            "CHECKPOINT_FILE",
+            "kafka-bridge.timetoken",
+        ),
+        checkpoint_topic: fetch_env_var_or("KAFKA_CHECKPOINT_TOPIC", ""),
+        backfill: fetch_env_var_or("PUBNUB_BACKFILL", "false") == "true",
+        presence_topic: fetch_env_var_or("KAFKA_PRESENCE_TOPIC", ""),
+        presence_table_topic: fetch_env_var_or(
+            "KAFKA_PRESENCE_TABLE_TOPIC",
+            "",
+        ),
+        heartbeat: fetch_env_number_or("PUBNUB_HEARTBEAT", 0),
+        log_payloads: fetch_env_var_or("LOG_PAYLOADS", "false") == "true",
         #[cfg(feature = "sasl-plain")]
         sasl_cfg: SASLConfig {
             username: fetch_env_var("SASL_USERNAME"),
@@ -60,7 +143,7 @@ fn environment_variables() -> Configuration {
 impl std::fmt::Display for Configuration {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let channel = if self.pubnub_channel_root.is_empty() {
-            self.pubnub_channel.to_string()
+            self.pubnub_channel.clone()
         } else {
             format!(
                 "{root}.{channel}",
@@ -78,8 +161,8 @@ impl std::fmt::Display for Configuration {
             domain=domain,
             url=url,
             channel=channel,
-            sub=self.subscribe_key,
-            pub=self.publish_key,
+            sub=redact::secret(&self.subscribe_key),
+            pub=redact::secret(&self.publish_key),
         )
     }
 }
@@ -88,11 +171,213 @@ fn fetch_env_var(name: &str) -> String {
     if let Ok(value) = env::var(name) {
         value
     } else {
-        eprintln!("Missing '{}' Environmental Variable", name);
+        eprintln!("Missing '{name}' Environmental Variable");
         process::exit(1);
     }
 }
 
+fn fetch_env_var_or(name: &str, default: &str) -> String {
+    env::var(name).unwrap_or_else(|_| default.into())
+}
+
+/// Auth key file, taking precedence over a fixed auth key.
+fn fetch_auth_key() -> Option<AuthKey> {
+    let file = fetch_env_var_or("PUBNUB_AUTH_KEY_FILE", "");
+    let key = fetch_env_var_or("PUBNUB_AUTH_KEY", "");
+    if !file.is_empty() {
+        Some(AuthKey::File(file.into()))
+    } else if !key.is_empty() {
+        Some(AuthKey::Key(key))
+    } else {
+        None
+    }
+}
+
+/// Bridge UUID, generated on first start and kept in a file so every
+/// restart reuses it. Exits when the generated one can't be kept.
+fn fetch_uuid() -> String {
+    let uuid = fetch_env_var_or("PUBNUB_UUID", "");
+    if !uuid.is_empty() {
+        return uuid;
+    }
+    let path = fetch_env_var_or("PUBNUB_UUID_FILE", "kafka-bridge.uuid");
+    if let Ok(uuid) = std::fs::read_to_string(&path) {
+        if !uuid.trim().is_empty() {
+            return uuid.trim().into();
+        }
+    }
+
+    // Random version 4 UUID
+    let mut bytes = [0_u8; 16];
+    openssl::rand::rand_bytes(&mut bytes).expect("Random UUID");
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    });
+    let uuid = format!(
+        "kafka-bridge-{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    );
+    if let Err(error) = std::fs::write(&path, &uuid) {
+        eprintln!(
+            "Invalid 'PUBNUB_UUID_FILE' Environmental Variable: {error}"
+        );
+        process::exit(1);
+    }
+    uuid
+}
+
+/// Publish options of every Kafka topic, from a JSON object of options
+/// keyed by topic.
+fn fetch_publish_options() -> HashMap<String, PublishOptions> {
+    let options = fetch_env_var_or("PUBNUB_PUBLISH_OPTIONS", "{}");
+    let parsed = match json::parse(&options) {
+        Ok(parsed) if parsed.is_object() => parsed,
+        _ => {
+            eprintln!("Invalid 'PUBNUB_PUBLISH_OPTIONS' Environmental Variable: Not a JSON object");
+            process::exit(1);
+        }
+    };
+    parsed
+        .entries()
+        .map(|(topic, options)| match options.dump().parse() {
+            Ok(options) => (topic.to_string(), options),
+            Err(error) => {
+                eprintln!("Invalid 'PUBNUB_PUBLISH_OPTIONS' Environmental Variable: {topic}: {error}");
+                process::exit(1);
+            }
+        })
+        .collect()
+}
+
+/// Cryptor for the cipher key, `None` without one.
+fn fetch_cryptor() -> Option<Cryptor> {
+    let cipher_key = fetch_env_var_or("PUBNUB_CIPHER_KEY", "");
+    if cipher_key.is_empty() {
+        return None;
+    }
+    let mode = match fetch_env_var_or("PUBNUB_CIPHER_MODE", "legacy").as_str()
+    {
+        "legacy" => Mode::Legacy {
+            random_iv: fetch_env_var_or("PUBNUB_CIPHER_RANDOM_IV", "true")
+                == "true",
+        },
+        "aes-cbc" => Mode::AesCbc,
+        _ => {
+            eprintln!("Invalid 'PUBNUB_CIPHER_MODE' Environmental Variable");
+            process::exit(1);
+        }
+    };
+    Some(Cryptor::new(&cipher_key, mode))
+}
+
+/// Checkpoint store of the subscribe timetoken, the compacted Kafka topic
+/// when one is set and the local file otherwise.
+fn checkpoint_store(
+    config: &Configuration,
+) -> Result<Box<dyn Store>, checkpoint::Error> {
+    if config.checkpoint_topic.is_empty() {
+        return Ok(Box::new(FileStore::new(&config.checkpoint_file)));
+    }
+
+    #[cfg(not(any(feature = "sasl-plain", feature = "sasl-ssl")))]
+    let store = KafkaStore::new(
+        &config.kafka_brokers,
+        &config.checkpoint_topic,
+        &config.kafka_group,
+    )?;
+
+    #[cfg(any(feature = "sasl-plain", feature = "sasl-ssl"))]
+    let store = KafkaStore::new_with_sasl(
+        &config.kafka_brokers,
+        &config.checkpoint_topic,
+        &config.kafka_group,
+        &config.sasl_cfg,
+    )?;
+
+    Ok(Box::new(store))
+}
+
+/// Timetoken saved before the last shutdown, `None` on first start.
+/// Retries outages and exits on a checkpoint it can't read, the next save
+/// would replace it and lose the messages since.
+async fn load_checkpoint(config: &Configuration) -> Option<Timetoken> {
+    loop {
+        let loaded = match checkpoint_store(config) {
+            Ok(mut store) => store.load().await,
+            Err(error) => Err(error),
+        };
+        match loaded {
+            Ok(timetoken) => return timetoken,
+            Err(error) if error.is_retryable() => {
+                println!("Retrying Checkpoint Load: {error}");
+                delay_for(Duration::from_secs(1)).await;
+            }
+            Err(error) => {
+                eprintln!("Checkpoint Load Failed: {error}");
+                process::exit(1);
+            }
+        }
+    }
+}
+
+fn fetch_env_number_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid '{name}' Environmental Variable");
+            process::exit(1);
+        }),
+        Err(_) => default,
+    }
+}
+
+/// Subscribe client for the configured channels, channel groups and
+/// keys, identified by the bridge `uuid`.
+fn subscribe_client(
+    config: &Configuration,
+    uuid: &str,
+) -> Result<pubnub::SubscribeClient, pubnub::Error> {
+    let mut pubnub = pubnub::SubscribeClient::new_with_tls(
+        &config.pubnub_host,
+        &config.pubnub_channel_root,
+        &config.pubnub_channel,
+        &config.subscribe_key,
+        &config.secret_key,
+        "kafka-bridge",
+        &config.pubnub_tls,
+    )?;
+    pubnub.set_reconnect_policy(config.pubnub_reconnect.clone());
+    pubnub.set_limits(config.pubnub_limits);
+    pubnub.set_auth_key(config.auth_key.clone());
+    pubnub.set_cryptor(config.cryptor.clone());
+    pubnub.set_publish_key(&config.publish_key);
+    pubnub.set_uuid(uuid);
+    pubnub
+        .set_heartbeat(Some(config.heartbeat).filter(|&timeout| timeout > 0));
+    pubnub.set_presence(!config.presence_topic.is_empty());
+    pubnub.set_channels(
+        &config
+            .pubnub_channel
+            .split(',')
+            .filter(|channel| !channel.is_empty())
+            .collect::<Vec<_>>(),
+    );
+    pubnub.set_channel_groups(
+        &config
+            .pubnub_channel_groups
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>(),
+    );
+    Ok(pubnub)
+}
+
 // Receive messages from Kafka
 // Consumes messages on Kafka topic and sends to MPSC PubNub Publisher
 async fn run_async_kafka_consumer(
@@ -120,21 +405,58 @@ async fn run_async_kafka_consumer(
         let mut kafka = match kafka {
             Ok(kafka) => kafka,
             Err(error) => {
-                println!("Retrying Consumer Connection {:?}", error);
-                delay_for(Duration::from_millis(1000)).await;
+                println!("Retrying Consumer Connection: {error}");
+                delay_for(Duration::from_secs(1)).await;
                 continue;
             }
         };
 
         // Send KAFKA Messages to pubnub_publish_rx via kafka_message_tx
-        kafka.consume().await.expect("Consuming failed");
+        if let Err(error) = kafka.consume().await {
+            println!("Restarting Consumer: {error}");
+            delay_for(Duration::from_secs(1)).await;
+        }
     }
 }
 
+/// Produces a presence event into the presence topic and updates the
+/// who is online table, removing the uuid once it left.
+async fn produce_presence(
+    kafka: &mut kafka::PublishClient,
+    config: &Configuration,
+    event: &pubnub::PresenceEvent,
+) -> Result<(), kafka::Error> {
+    let data = event.to_json();
+    let timetoken = Some(event.timetoken);
+    kafka
+        .produce_keyed(
+            &config.presence_topic,
+            &event.uuid,
+            Some(&data),
+            timetoken,
+        )
+        .await?;
+    if config.presence_table_topic.is_empty() || event.uuid.is_empty() {
+        return Ok(());
+    }
+    let online = match event.action.as_str() {
+        "leave" | "timeout" => None,
+        _ => Some(data.as_str()),
+    };
+    kafka
+        .produce_keyed(
+            &config.presence_table_topic,
+            &event.uuid,
+            online,
+            timetoken,
+        )
+        .await
+}
+
 // Send messages to Kafka
 // Reads MPSC from PubNub Subscriptions and Sends to Kafka
 async fn run_async_kafka_producer(
-    kafka_publish_rx: mpsc::Receiver<pubnub::Message>,
+    kafka_publish_rx: mpsc::Receiver<pubnub::Event>,
 ) {
     let mut kafka_publish_rx = kafka_publish_rx;
     loop {
@@ -155,24 +477,81 @@ async fn run_async_kafka_producer(
 
         let mut kafka = match kafka {
             Ok(kafka) => kafka,
-            Err(_error) => {
-                delay_for(Duration::from_millis(1000)).await;
+            Err(error) => {
+                println!("Retrying Producer Connection: {error}");
+                delay_for(Duration::from_secs(1)).await;
                 continue;
             }
         };
+        let mut checkpoint = checkpoint_store(&config)
+            .map_err(|error| println!("Checkpoint Disabled: {error}"))
+            .ok();
 
         loop {
-            let message: kafka_bridge::pubnub::Message = kafka_publish_rx
+            let event: pubnub::Event = kafka_publish_rx
                 .recv()
                 .await
                 .expect("Async MPSC Channel receiver");
-            match kafka.produce(&message.data).await {
-                Ok(()) => {}
-                Err(_error) => {
-                    delay_for(Duration::from_millis(1000)).await;
+            let timetoken = match &event {
+                pubnub::Event::Message(message) => message.timetoken,
+                pubnub::Event::Presence(presence) => presence.timetoken,
+                // Keep ciphertext out of Kafka, likely a wrong cipher key
+                pubnub::Event::Undecryptable(message, error) => {
+                    println!(
+                        "Skipping Undecryptable Message {} on {}: {error}",
+                        message.timetoken, message.channel
+                    );
+                    continue;
                 }
             };
+
+            // Retry outages, the subscription already moved past the event
+            let mut produced =
+                produce_event(&mut kafka, &config, &event).await;
+            while let Err(error) = &produced {
+                if !error.is_retryable() {
+                    break;
+                }
+                println!("Retrying Produce: {error}");
+                delay_for(Duration::from_secs(1)).await;
+                produced = produce_event(&mut kafka, &config, &event).await;
+            }
+            match produced {
+                // Checkpoint only messages safely in Kafka
+                Ok(()) => {
+                    if let Some(store) = &mut checkpoint {
+                        if let Err(error) = store.save(timetoken).await {
+                            println!("Checkpoint Failed: {error}");
+                        }
+                    }
+                }
+                // Later checkpoints would skip the dropped message on restart
+                Err(error) => {
+                    println!("Dropping Message: {error}");
+                    if checkpoint.take().is_some() {
+                        println!("Checkpoint Stopped Before Dropped Message");
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Produces a message or presence `event`, undecryptable messages are
+/// skipped before reaching Kafka.
+async fn produce_event(
+    kafka: &mut kafka::PublishClient,
+    config: &Configuration,
+    event: &pubnub::Event,
+) -> Result<(), kafka::Error> {
+    match event {
+        pubnub::Event::Message(message) => {
+            kafka.produce(&message.data, Some(message.timetoken)).await
         }
+        pubnub::Event::Presence(presence) => {
+            produce_presence(kafka, config, presence).await
+        }
+        pubnub::Event::Undecryptable(..) => Ok(()),
     }
 }
 
@@ -180,6 +559,7 @@ async fn run_async_kafka_producer(
 // Receives messages from MPSC from Kafka and Publishes to PubNub
 async fn run_async_pubnub_publisher(
     pubnub_publish_rx: mpsc::Receiver<kafka::Message>,
+    uuid: String,
 ) {
     let mut pubnub_publish_rx = pubnub_publish_rx;
     let config = environment_variables();
@@ -191,20 +571,33 @@ async fn run_async_pubnub_publisher(
     let agent = "kafka-bridge";
 
     loop {
-        let mut pubnub = match pubnub::PublishClient::new(
+        let mut pubnub = match pubnub::PublishClient::new_with_tls(
             host,
             root,
             publish_key,
             subscribe_key,
             secret_key,
             agent,
+            &config.pubnub_tls,
         ) {
             Ok(pubnub) => pubnub,
-            Err(_error) => {
-                delay_for(Duration::from_millis(1000)).await;
+            Err(error) => {
+                println!("Retrying Publisher Client: {error}");
+                delay_for(Duration::from_secs(1)).await;
                 continue;
             }
         };
+        pubnub.set_reconnect_policy(config.pubnub_reconnect.clone());
+        pubnub.set_limits(config.pubnub_limits);
+        pubnub.set_auth_key(config.auth_key.clone());
+        pubnub.set_cryptor(config.cryptor.clone());
+        pubnub.set_uuid(&uuid);
+        if !config.publish_meta.is_empty() {
+            if let Err(error) = pubnub.set_meta(&config.publish_meta) {
+                eprintln!("Invalid 'PUBNUB_PUBLISH_META' Environmental Variable: {error}");
+                process::exit(1);
+            }
+        }
 
         // Message Receiver Loop
         loop {
@@ -214,20 +607,168 @@ async fn run_async_pubnub_publisher(
                 .expect("MPSC Channel Receiver");
             let channel = &message.topic;
             let data = &message.data;
+            let options = config
+                .publish_options
+                .get(&message.topic)
+                .cloned()
+                .unwrap_or_default();
 
             // Retry Loop on Failure
             loop {
-                match pubnub.publish(channel, data) {
+                match pubnub
+                    .publish_with_options(channel, data, &options)
+                    .await
+                {
                     Ok(_timetoken) => break,
-                    Err(_error) => {
-                        delay_for(Duration::from_millis(1000)).await
+                    Err(error) if error.is_retryable() => {
+                        delay_for(Duration::from_secs(1)).await;
+                    }
+                    Err(error) => {
+                        println!("Dropping Message: {error}");
+                        break;
                     }
-                };
+                }
             }
         }
     }
 }
 
+// Receive messages from PubNub
+// Saves messages into MPSC for Kafka Producer Task
+// Once the message is saved to the MPSC queue, a worker
+// will pick up the message and publish it to Kafka cluster
+// using a kafka.produce() method.
+async fn run_async_pubnub_subscriber(
+    pubnub_message_tx: mpsc::Sender<pubnub::Event>,
+    uuid: String,
+) {
+    let mut pubnub_message_tx = pubnub_message_tx;
+    let config = environment_variables();
+    let timetoken = load_checkpoint(&config).await;
+    if config.backfill
+        && (config.pubnub_channel.contains('*')
+            || !config.pubnub_channel_groups.is_empty())
+    {
+        println!("Backfill Limited: wildcard channels and channel groups only recover the subscribe cache");
+    }
+
+    loop {
+        let mut pubnub = match subscribe_client(&config, &uuid) {
+            Ok(pubnub) => pubnub,
+            Err(error) => {
+                println!("Retrying Subscriber Client: {error}");
+                delay_for(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        if let Some(timetoken) = timetoken {
+            pubnub.set_timetoken(timetoken);
+        }
+        pubnub.set_backfill(config.backfill);
+        if !config.filter_expression.is_empty() {
+            if let Err(error) =
+                pubnub.set_filter_expression(&config.filter_expression)
+            {
+                eprintln!("Invalid 'PUBNUB_FILTER_EXPRESSION' Environmental Variable: {error}");
+                process::exit(1);
+            }
+        }
+
+        let mut attempt = 0;
+        loop {
+            let event = match pubnub.next_event().await {
+                Ok(event) => event,
+                // Back off on outages, pause longest when retrying can't
+                // help until the configuration or permissions change
+                Err(error) => {
+                    let delay = if error.is_retryable() {
+                        config.pubnub_reconnect.delay(attempt)
+                    } else {
+                        config.pubnub_reconnect.max_delay
+                    };
+                    attempt = attempt.saturating_add(1);
+                    println!("Subscribe Failed: {error}");
+                    delay_for(delay).await;
+                    continue;
+                }
+            };
+            attempt = 0;
+
+            pubnub_message_tx
+                .send(event)
+                .await
+                .map_err(|_| ())
+                .expect("KAFKA mpsc::channel channel write");
+        }
+    }
+}
+
+// Keep the bridge present on PubNub
+// Sends presence heartbeats on a separate connection, so they don't cancel
+// the long running subscribe request
+async fn run_async_pubnub_heartbeat(uuid: String) {
+    let config = environment_variables();
+    if config.heartbeat == 0 {
+        return;
+    }
+    let interval = u64::from((config.heartbeat / 2).saturating_sub(1).max(1));
+
+    loop {
+        let mut pubnub = match subscribe_client(&config, &uuid) {
+            Ok(pubnub) => pubnub,
+            Err(error) => {
+                println!("Retrying Subscriber Client: {error}");
+                delay_for(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        loop {
+            delay_for(Duration::from_secs(interval)).await;
+            if let Err(error) = pubnub.heartbeat().await {
+                println!("Heartbeat Failed: {error}");
+            }
+        }
+    }
+}
+
+/// Tells `PubNub` the bridge left its channels, so presence sees it go
+/// instead of timing out.
+async fn leave_pubnub(config: &Configuration, uuid: &str) {
+    let left = match subscribe_client(config, uuid) {
+        Ok(mut pubnub) => {
+            tokio::time::timeout(Duration::from_secs(5), pubnub.leave())
+                .await
+                .unwrap_or(Err(pubnub::Error::Socket(socket::Error::Timeout)))
+        }
+        Err(error) => Err(error),
+    };
+    if let Err(error) = left {
+        println!("Leave Failed: {error}");
+    }
+}
+
+/// Waits for Ctrl-C or, on Unix, the `SIGTERM` container runtimes and
+/// service managers stop the bridge with.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(error) => println!("SIGTERM Handler Failed: {error}"),
+        }
+    }
+    if let Err(error) = tokio::signal::ctrl_c().await {
+        println!("Ctrl-C Handler Failed: {error}");
+    }
+}
+
 #[tokio::main]
 // =-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=
 // Main Loop
@@ -235,69 +776,47 @@ async fn run_async_pubnub_publisher(
 async fn main() {
     // Async Channels
     let (kafka_message_tx, pubnub_publish_rx) = mpsc::channel(100);
-    let (mut pubnub_message_tx, kafka_publish_rx) = mpsc::channel(100);
-
-    let rt_handle = tokio::runtime::Handle::current();
-
-    // Receive messages from PubNub
-    // Saves messages into MPSC for Kafka Producer Thread
-    // Once the message is saved to the MPSC queue, a worker
-    // will pick up the message and publish it to Kafka cluster
-    // using a kafka.produce() method.
-    let pubnub_subscriber_thread = thread::Builder::new()
-        .name("PubNub Subscriber Thread".into())
-        .spawn(move || loop {
-            use kafka_bridge::pubnub;
-
-            let config = environment_variables();
-            let host = &config.pubnub_host;
-            let root = &config.pubnub_channel_root;
-            let channel = &config.pubnub_channel;
-            let subscribe_key = &config.subscribe_key;
-            let secret_key = &config.secret_key;
-            let agent = "kafka-bridge";
-
-            let mut pubnub = match pubnub::SubscribeClient::new(
-                host,
-                root,
-                channel,
-                subscribe_key,
-                secret_key,
-                agent,
-            ) {
-                Ok(pubnub) => pubnub,
-                Err(_error) => {
-                    thread::sleep(time::Duration::new(1, 0));
-                    continue;
-                }
-            };
-
-            loop {
-                let message = match pubnub.next_message() {
-                    Ok(message) => message,
-                    Err(_error) => continue,
-                };
-
-                rt_handle
-                    .block_on(pubnub_message_tx.send(message))
-                    .map_err(|_| ())
-                    .expect("KAFKA mpsc::channel channel write");
-            }
-        });
+    let (pubnub_message_tx, kafka_publish_rx) = mpsc::channel(100);
 
     // Print Follow-on Instructions
     let config = environment_variables();
-    println!("{{\"info\":\"Dashboard: {}\"}}", config);
+    redact::set_log_payloads(config.log_payloads);
+    println!("{{\"info\":\"Dashboard: {config}\"}}");
 
-    tokio::join!(
-        run_async_kafka_consumer(kafka_message_tx),
-        run_async_kafka_producer(kafka_publish_rx),
-        run_async_pubnub_publisher(pubnub_publish_rx)
-    );
+    // Every PubNub client of the bridge shares one UUID, so presence sees
+    // a single bridge
+    let uuid = fetch_uuid();
+
+    // Every bridge direction runs as its own task, so one busy direction
+    // can't stall the others. The runtime cancels them on shutdown.
+    let bridge = async {
+        tokio::try_join!(
+            tokio::spawn(run_async_kafka_consumer(kafka_message_tx)),
+            tokio::spawn(run_async_kafka_producer(kafka_publish_rx)),
+            tokio::spawn(run_async_pubnub_publisher(
+                pubnub_publish_rx,
+                uuid.clone()
+            )),
+            tokio::spawn(run_async_pubnub_subscriber(
+                pubnub_message_tx,
+                uuid.clone()
+            )),
+            tokio::spawn(run_async_pubnub_heartbeat(uuid.clone()))
+        )
+    };
 
-    // The Threads Gather
-    pubnub_subscriber_thread
-        .expect("PubNub Subscriber thread builder join handle")
-        .join()
-        .expect("Joining PubNub Subscriber Thread");
+    tokio::select! {
+        // Directions only end by panicking, exit so the bridge restarts
+        // instead of running without them
+        bridge = bridge => {
+            if let Err(error) = bridge {
+                eprintln!("Bridge Task Failed: {error}");
+                process::exit(1);
+            }
+        }
+        () = shutdown_signal() => {
+            println!("{{\"info\":\"Shutting down\"}}");
+            leave_pubnub(&config, &uuid).await;
+        }
+    }
 }
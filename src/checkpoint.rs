@@ -0,0 +1,383 @@
+#[cfg(any(feature = "sasl-plain", feature = "sasl-ssl"))]
+use crate::kafka::SASLConfig;
+use crate::timetoken::Timetoken;
+use async_trait::async_trait;
+use futures_util::stream::StreamExt;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::stream_consumer::StreamConsumer;
+use rdkafka::consumer::Consumer;
+use rdkafka::error::KafkaError;
+use rdkafka::message::Message;
+use rdkafka::metadata::MetadataTopic;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::topic_partition_list::{Offset, TopicPartitionList};
+use rdkafka::util::Timeout;
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::{fmt, fs, io};
+
+/// Wait for Kafka metadata, watermarks and checkpoint records.
+const KAFKA_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Checkpoint failure with its cause.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing the checkpoint file failed.
+    Io(io::Error),
+    /// Reading or writing the checkpoint topic failed.
+    Kafka(KafkaError),
+    /// The checkpoint topic was not read to its end in time.
+    Timeout,
+    /// The stored checkpoint is not a timetoken.
+    Malformed(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "Checkpoint file failed: {error}"),
+            Error::Kafka(error) => {
+                write!(f, "Checkpoint topic failed: {error}")
+            }
+            Error::Timeout => write!(f, "Checkpoint topic timed out"),
+            Error::Malformed(value) => {
+                write!(f, "Invalid checkpoint timetoken {value:?}")
+            }
+        }
+    }
+}
+
+impl Error {
+    /// Returns `true` when repeating the operation may succeed: Kafka
+    /// outages and timeouts. Unreadable files, malformed checkpoints and
+    /// Kafka configuration failures need an operator.
+    #[must_use]
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Kafka(
+                KafkaError::ClientConfig(..) | KafkaError::ClientCreation(_),
+            )
+            | Error::Io(_)
+            | Error::Malformed(_) => false,
+            Error::Kafka(_) | Error::Timeout => true,
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            Error::Kafka(error) => Some(error),
+            Error::Timeout | Error::Malformed(_) => None,
+        }
+    }
+}
+
+/// # Checkpoint Store
+///
+/// Keeps the timetoken of the last `PubNub` message produced into Kafka,
+/// so the subscription resumes from it after a restart.
+#[async_trait]
+pub trait Store: Send {
+    /// Returns the saved timetoken, `None` before the first save.
+    ///
+    /// # Errors
+    ///
+    /// Returns the [`Error`] that failed reading the checkpoint.
+    async fn load(&mut self) -> Result<Option<Timetoken>, Error>;
+
+    /// Replaces the saved timetoken.
+    ///
+    /// # Errors
+    ///
+    /// Returns the [`Error`] that failed writing the checkpoint.
+    async fn save(&mut self, timetoken: Timetoken) -> Result<(), Error>;
+}
+
+/// Runs the file or Kafka client `operation` on the blocking thread pool.
+async fn blocking<T: Send + 'static>(
+    operation: impl FnOnce() -> Result<T, Error> + Send + 'static,
+) -> Result<T, Error> {
+    match tokio::task::spawn_blocking(operation).await {
+        Ok(result) => result,
+        Err(error) => Err(Error::Io(io::Error::other(error))),
+    }
+}
+
+/// Checks a stored `value` is a timetoken, empty values are unset.
+fn timetoken(value: &str) -> Result<Option<Timetoken>, Error> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value
+        .parse()
+        .map(Some)
+        .map_err(|_| Error::Malformed(value.into()))
+}
+
+// =-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=
+/// # File Checkpoint Store
+///
+/// Keeps the timetoken in a local file, replaced atomically on every
+/// save so a crash never leaves it half written. The new timetoken is
+/// flushed to disk before it replaces the previous one.
+///
+/// ```
+/// use kafka_bridge::checkpoint::{FileStore, Store};
+/// use kafka_bridge::timetoken::Timetoken;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let path = std::env::temp_dir().join("kafka-bridge-doc.timetoken");
+///     let mut store = FileStore::new(&path);
+///     let timetoken = Timetoken::new(15_000_000_000_000_000);
+///     store.save(timetoken).await.expect("save");
+///     assert_eq!(store.load().await.expect("load"), Some(timetoken));
+///     std::fs::remove_file(&path).expect("remove");
+/// }
+/// ```
+// =-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=
+#[derive(Clone, Debug)]
+pub struct FileStore {
+    path: PathBuf,
+}
+
+impl FileStore {
+    #[must_use]
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl Store for FileStore {
+    async fn load(&mut self) -> Result<Option<Timetoken>, Error> {
+        let path = self.path.clone();
+        let value = blocking(move || match fs::read_to_string(path) {
+            Ok(value) => Ok(Some(value)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(Error::Io(error)),
+        })
+        .await?;
+        value.map_or(Ok(None), |value| timetoken(&value))
+    }
+
+    async fn save(&mut self, timetoken: Timetoken) -> Result<(), Error> {
+        let path = self.path.clone();
+        blocking(move || {
+            let mut temporary = path.clone().into_os_string();
+            temporary.push(".tmp");
+            let mut file = fs::File::create(&temporary).map_err(Error::Io)?;
+            file.write_all(timetoken.to_string().as_bytes())
+                .and_then(|()| file.sync_all())
+                .and_then(|()| fs::rename(&temporary, &path))
+                .map_err(Error::Io)
+        })
+        .await
+    }
+}
+
+// =-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=
+/// # Kafka Checkpoint Store
+///
+/// Keeps the timetoken as the latest record under `key` in a compacted
+/// Kafka topic, shared by bridges running on different hosts.
+///
+/// ```no_run
+/// use kafka_bridge::checkpoint::{KafkaStore, Store};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let brokers = ["0.0.0.0:9094".to_string()];
+///     let mut store = KafkaStore::new(&brokers, "checkpoints", "bridge")
+///         .expect("Kafka checkpoint store");
+///     let _timetoken = store.load().await;
+/// }
+/// ```
+// =-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=
+pub struct KafkaStore {
+    config: ClientConfig,
+    producer: FutureProducer,
+    topic: String,
+    key: String,
+}
+
+impl KafkaStore {
+    /// Creates a new [`KafkaStore`] for the `key` record of `topic`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Kafka`] when the producer can't be created.
+    pub fn new(
+        brokers: &[String],
+        topic: &str,
+        key: &str,
+    ) -> Result<Self, Error> {
+        Self::with_config(ClientConfig::new(), brokers, topic, key)
+    }
+
+    #[cfg(any(feature = "sasl-plain", feature = "sasl-ssl"))]
+    /// Creates a new [`KafkaStore`] using SASL with `SASL_PLAINTEXT` or `SASL_SSL` depending on config.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Kafka`] when the producer can't be created.
+    pub fn new_with_sasl(
+        brokers: &[String],
+        topic: &str,
+        key: &str,
+        sasl_cfg: &SASLConfig,
+    ) -> Result<Self, Error> {
+        Self::with_config(ClientConfig::from(sasl_cfg), brokers, topic, key)
+    }
+
+    fn with_config(
+        mut config: ClientConfig,
+        brokers: &[String],
+        topic: &str,
+        key: &str,
+    ) -> Result<Self, Error> {
+        config.set("bootstrap.servers", &brokers.join(","));
+        let producer = config
+            .clone()
+            .set("acks", "all")
+            .create()
+            .map_err(Error::Kafka)?;
+        Ok(Self {
+            config,
+            producer,
+            topic: topic.into(),
+            key: key.into(),
+        })
+    }
+}
+
+#[async_trait]
+impl Store for KafkaStore {
+    /// Reads every partition of the topic up to its end, keeping the
+    /// latest record under the key.
+    async fn load(&mut self) -> Result<Option<Timetoken>, Error> {
+        let consumer: StreamConsumer = self
+            .config
+            .clone()
+            .set("group.id", &format!("{}-checkpoint", self.key))
+            .set("enable.auto.commit", "false")
+            .set("enable.partition.eof", "true")
+            .create()
+            .map_err(Error::Kafka)?;
+
+        // Every non-empty partition, the metadata requests block
+        let topic = self.topic.clone();
+        let (consumer, mut remaining) = blocking(move || {
+            let metadata = consumer
+                .fetch_metadata(Some(&topic), KAFKA_TIMEOUT)
+                .map_err(Error::Kafka)?;
+            let mut assignment = TopicPartitionList::new();
+            let mut remaining = HashSet::new();
+            for partition in
+                metadata.topics().iter().flat_map(MetadataTopic::partitions)
+            {
+                let (low, high) = consumer
+                    .fetch_watermarks(&topic, partition.id(), KAFKA_TIMEOUT)
+                    .map_err(Error::Kafka)?;
+                if high > low {
+                    assignment.add_partition_offset(
+                        &topic,
+                        partition.id(),
+                        Offset::Beginning,
+                    );
+                    remaining.insert(partition.id());
+                }
+            }
+            if !remaining.is_empty() {
+                consumer.assign(&assignment).map_err(Error::Kafka)?;
+            }
+            Ok((consumer, remaining))
+        })
+        .await?;
+        if remaining.is_empty() {
+            return Ok(None);
+        }
+
+        let mut latest = None;
+        let mut stream = consumer.start();
+        while !remaining.is_empty() {
+            // The end event also covers offsets of transaction markers and
+            // compacted records, never read as messages
+            let message = match tokio::time::timeout(
+                KAFKA_TIMEOUT,
+                stream.next(),
+            )
+            .await
+            {
+                Ok(Some(Err(KafkaError::PartitionEOF(partition)))) => {
+                    remaining.remove(&partition);
+                    continue;
+                }
+                Ok(Some(message)) => message.map_err(Error::Kafka)?,
+                Ok(None) | Err(_) => return Err(Error::Timeout),
+            };
+            if message.key() == Some(self.key.as_bytes()) {
+                // Empty payloads delete the checkpoint
+                latest = message.payload().map(|payload| {
+                    String::from_utf8_lossy(payload).into_owned()
+                });
+            }
+        }
+        match latest {
+            Some(value) => timetoken(&value),
+            None => Ok(None),
+        }
+    }
+
+    async fn save(&mut self, timetoken: Timetoken) -> Result<(), Error> {
+        self.producer
+            .send(
+                FutureRecord::to(&self.topic)
+                    .key(&self.key)
+                    .payload(&timetoken.to_string()),
+                Timeout::After(KAFKA_TIMEOUT),
+            )
+            .await
+            .map(|_| ())
+            .map_err(|(error, _)| Error::Kafka(error))
+    }
+}
+
+#[cfg(test)]
+mod checkpoint_tests {
+    use super::{Error, FileStore, Store};
+    use crate::timetoken::Timetoken;
+
+    #[tokio::test]
+    async fn file_store_ok() {
+        let path = std::env::temp_dir()
+            .join(format!("kafka-bridge-checkpoint-{}", std::process::id()));
+        let mut store = FileStore::new(&path);
+        assert_eq!(store.load().await.expect("load"), None);
+
+        store.save(Timetoken::new(100)).await.expect("save");
+        store.save(Timetoken::new(200)).await.expect("save");
+        assert_eq!(
+            store.load().await.expect("load"),
+            Some(Timetoken::new(200))
+        );
+
+        std::fs::write(&path, "not a timetoken").expect("write");
+        let error = store.load().await.expect_err("malformed");
+        assert!(matches!(error, Error::Malformed(_)));
+        assert!(!error.is_retryable());
+        std::fs::remove_file(&path).expect("remove");
+
+        // A directory can't be read as the checkpoint
+        let mut store = FileStore::new(std::env::temp_dir());
+        let error = store.load().await.expect_err("unreadable");
+        assert!(matches!(error, Error::Io(_)));
+        assert!(!error.is_retryable());
+        assert!(Error::Timeout.is_retryable());
+    }
+}
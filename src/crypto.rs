@@ -0,0 +1,293 @@
+use openssl::error::ErrorStack;
+use openssl::symm::{self, Cipher};
+use std::fmt;
+
+/// Initialization vector of legacy messages without a random one.
+const STATIC_IV: &[u8; 16] = b"0123456789012345";
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+/// Marks messages carrying a cryptor header.
+const SENTINEL: &[u8; 4] = b"PNED";
+
+/// Identifier of the AES-256-CBC cryptor in the header.
+const AES_CBC_ID: &[u8; 4] = b"ACRH";
+
+/// Message encryption or decryption failure with its cause.
+#[derive(Debug)]
+pub enum Error {
+    /// The ciphertext is not valid base64 or has an invalid header.
+    Malformed(String),
+    /// Encryption or decryption failed, likely a different cipher key.
+    Cipher(ErrorStack),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Malformed(cause) => {
+                write!(f, "Malformed encrypted message: {cause}")
+            }
+            Error::Cipher(error) => write!(f, "Cipher failed: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Cipher(error) => Some(error),
+            Error::Malformed(_) => None,
+        }
+    }
+}
+
+/// Format of encrypted messages, matching the `PubNub` SDK crypto
+/// modules.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// AES-256-CBC keyed with the first 32 hex digits of the SHA-256 of
+    /// the cipher key. The random IV is prepended to the ciphertext,
+    /// otherwise the static `0123456789012345` IV is used.
+    Legacy { random_iv: bool },
+    /// AES-256-CBC keyed with the SHA-256 of the cipher key, behind a
+    /// `PNED` cryptor header carrying the random IV.
+    AesCbc,
+}
+
+/// # Message Cryptor
+///
+/// Encrypts published messages and decrypts received ones with a
+/// `PubNub` cipher key. Messages are decrypted from either format,
+/// whatever [`Mode`] they're encrypted with.
+///
+/// ```
+/// use kafka_bridge::crypto::{Cryptor, Mode};
+///
+/// let cryptor = Cryptor::new("enigma", Mode::Legacy { random_iv: false });
+/// let encrypted = cryptor.encrypt(b"yay!").expect("encrypt");
+/// assert_eq!(encrypted, "q/xJqqN6qbiZMXYmiQC1Fw==");
+/// assert_eq!(cryptor.decrypt(&encrypted).expect("decrypt"), b"yay!");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Cryptor {
+    legacy_key: Vec<u8>,
+    key: Vec<u8>,
+    mode: Mode,
+}
+
+impl Cryptor {
+    #[must_use]
+    pub fn new(cipher_key: &str, mode: Mode) -> Self {
+        let key = openssl::sha::sha256(cipher_key.as_bytes());
+        // First 32 lowercase hex digits of the digest
+        let legacy_key = key[..16]
+            .iter()
+            .flat_map(|byte| {
+                [HEX[usize::from(byte >> 4)], HEX[usize::from(byte & 15)]]
+            })
+            .collect();
+        Self {
+            legacy_key,
+            key: key.to_vec(),
+            mode,
+        }
+    }
+
+    /// Encrypts `plaintext` into base64.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Cipher`] when encryption fails.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<String, Error> {
+        let encrypted = match self.mode {
+            Mode::Legacy { random_iv: false } => aes_cbc(
+                symm::Mode::Encrypt,
+                &self.legacy_key,
+                STATIC_IV,
+                plaintext,
+            )?,
+            Mode::Legacy { random_iv: true } => {
+                let iv = random_iv()?;
+                let mut encrypted = iv.to_vec();
+                encrypted.extend(aes_cbc(
+                    symm::Mode::Encrypt,
+                    &self.legacy_key,
+                    &iv,
+                    plaintext,
+                )?);
+                encrypted
+            }
+            Mode::AesCbc => {
+                let iv = random_iv()?;
+                let mut encrypted = SENTINEL.to_vec();
+                encrypted.push(1);
+                encrypted.extend(AES_CBC_ID);
+                encrypted.push(16);
+                encrypted.extend(&iv);
+                encrypted.extend(aes_cbc(
+                    symm::Mode::Encrypt,
+                    &self.key,
+                    &iv,
+                    plaintext,
+                )?);
+                encrypted
+            }
+        };
+        Ok(openssl::base64::encode_block(&encrypted))
+    }
+
+    /// Decrypts base64 `encrypted`, with a cryptor header or in the
+    /// legacy format of this cryptor's [`Mode`].
+    ///
+    /// # Errors
+    ///
+    /// * [`Error::Malformed`] on invalid base64 or cryptor header
+    /// * [`Error::Cipher`] when decryption fails
+    pub fn decrypt(&self, encrypted: &str) -> Result<Vec<u8>, Error> {
+        let data = openssl::base64::decode_block(encrypted.trim())
+            .map_err(|_| Error::Malformed("Invalid base64".into()))?;
+        if data.starts_with(SENTINEL) {
+            return self.decrypt_header(&data);
+        }
+        match self.mode {
+            Mode::Legacy { random_iv: true } if data.len() > 16 => aes_cbc(
+                symm::Mode::Decrypt,
+                &self.legacy_key,
+                &data[..16],
+                &data[16..],
+            ),
+            Mode::Legacy { random_iv: true } => {
+                Err(Error::Malformed("Missing IV".into()))
+            }
+            _ => aes_cbc(
+                symm::Mode::Decrypt,
+                &self.legacy_key,
+                STATIC_IV,
+                &data,
+            ),
+        }
+    }
+
+    /// Decrypts `data` starting with a cryptor header: sentinel, version,
+    /// cryptor identifier, cryptor data size and cryptor data, the IV.
+    fn decrypt_header(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let malformed =
+            || Error::Malformed("Truncated cryptor header".into());
+        let version = *data.get(4).ok_or_else(malformed)?;
+        if version != 1 {
+            return Err(Error::Malformed(format!(
+                "Unknown cryptor header version {version}"
+            )));
+        }
+        let identifier = data.get(5..9).ok_or_else(malformed)?;
+        let (size, offset) = match *data.get(9).ok_or_else(malformed)? {
+            255 => {
+                let size = data.get(10..12).ok_or_else(malformed)?;
+                (usize::from(u16::from_be_bytes([size[0], size[1]])), 12)
+            }
+            size => (usize::from(size), 10),
+        };
+        let iv = data.get(offset..offset + size).ok_or_else(malformed)?;
+        if identifier != AES_CBC_ID || iv.len() != 16 {
+            return Err(Error::Malformed(format!(
+                "Unsupported cryptor {:?}",
+                String::from_utf8_lossy(identifier)
+            )));
+        }
+        aes_cbc(symm::Mode::Decrypt, &self.key, iv, &data[offset + size..])
+    }
+}
+
+fn aes_cbc(
+    mode: symm::Mode,
+    key: &[u8],
+    iv: &[u8],
+    data: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let cipher = Cipher::aes_256_cbc();
+    match mode {
+        symm::Mode::Encrypt => symm::encrypt(cipher, key, Some(iv), data),
+        symm::Mode::Decrypt => symm::decrypt(cipher, key, Some(iv), data),
+    }
+    .map_err(Error::Cipher)
+}
+
+fn random_iv() -> Result<[u8; 16], Error> {
+    let mut iv = [0; 16];
+    openssl::rand::rand_bytes(&mut iv).map_err(Error::Cipher)?;
+    Ok(iv)
+}
+
+#[cfg(test)]
+mod crypto_tests {
+    use super::{Cryptor, Error, Mode};
+
+    #[test]
+    fn legacy_ok() {
+        let cryptor =
+            Cryptor::new("enigma", Mode::Legacy { random_iv: false });
+        assert_eq!(
+            cryptor.encrypt(br#""yay!""#).expect("encrypt"),
+            "Wi24KS4pcTzvyuGOHubiXg=="
+        );
+        assert_eq!(
+            cryptor
+                .decrypt("Wi24KS4pcTzvyuGOHubiXg==")
+                .expect("decrypt"),
+            br#""yay!""#
+        );
+
+        let cryptor =
+            Cryptor::new("enigma", Mode::Legacy { random_iv: true });
+        assert_eq!(
+            cryptor
+                .decrypt("YWJjZGVmZ2hpamtsbW5vcCKfOgRVXsOvWcyHauF2X1I=")
+                .expect("decrypt"),
+            b"yay!"
+        );
+        let encrypted = cryptor.encrypt(b"yay!").expect("encrypt");
+        assert_ne!(encrypted, cryptor.encrypt(b"yay!").expect("encrypt"));
+        assert_eq!(cryptor.decrypt(&encrypted).expect("decrypt"), b"yay!");
+    }
+
+    #[test]
+    fn cryptor_header_ok() {
+        let encrypted = "UE5FRAFBQ1JIEGFiY2RlZmdoaWprbG1ub3AEmZgWsJ6DSJDE\
+                         hV0E5JW5/e3Yci+NjkehW2E/IbP2rA==";
+        // Headers are decrypted whatever the mode
+        for mode in &[Mode::AesCbc, Mode::Legacy { random_iv: false }] {
+            let cryptor = Cryptor::new("enigma", *mode);
+            assert_eq!(
+                cryptor.decrypt(encrypted).expect("decrypt"),
+                br#"{"hello":"world"}"#
+            );
+        }
+
+        let cryptor = Cryptor::new("enigma", Mode::AesCbc);
+        let encrypted = cryptor.encrypt(b"[1,2]").expect("encrypt");
+        assert!(encrypted.starts_with("UE5FRAFBQ1JIE"));
+        assert_eq!(cryptor.decrypt(&encrypted).expect("decrypt"), b"[1,2]");
+    }
+
+    #[test]
+    fn decrypt_error() {
+        let cryptor = Cryptor::new("enigma", Mode::AesCbc);
+        for encrypted in &["not base64!", "UE5FRAI=", "UE5FRAFBQ1JIEGFi"] {
+            assert!(
+                matches!(
+                    cryptor.decrypt(encrypted),
+                    Err(Error::Malformed(_))
+                ),
+                "{}",
+                encrypted
+            );
+        }
+
+        let cryptor =
+            Cryptor::new("enigma", Mode::Legacy { random_iv: false });
+        let other = Cryptor::new("other", Mode::Legacy { random_iv: false });
+        let encrypted = other.encrypt(b"secret").expect("encrypt");
+        assert!(matches!(cryptor.decrypt(&encrypted), Err(Error::Cipher(_))));
+    }
+}
@@ -0,0 +1,713 @@
+use crate::socket;
+use crate::transport::Transport;
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::Read;
+
+/// Content codings [`Response::read`] decodes, sent as the
+/// `Accept-Encoding` request header.
+pub const ACCEPT_ENCODING: &str = "gzip, deflate";
+
+/// Most interim `1xx` responses skipped before the final one.
+const MAX_INTERIM_RESPONSES: usize = 8;
+
+/// HTTP response failure with its cause.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading the response failed.
+    Socket(socket::Error),
+    /// The response is not valid HTTP/1.1.
+    Malformed(String),
+    /// The response exceeds the [`Limits`].
+    TooLarge(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Socket(error) => write!(f, "{error}"),
+            Error::Malformed(cause) => {
+                write!(f, "Malformed HTTP response: {cause}")
+            }
+            Error::TooLarge(cause) => {
+                write!(f, "HTTP response too large: {cause}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Socket(error) => Some(error),
+            Error::Malformed(_) | Error::TooLarge(_) => None,
+        }
+    }
+}
+
+impl From<socket::Error> for Error {
+    fn from(error: socket::Error) -> Self {
+        Error::Socket(error)
+    }
+}
+
+/// Reports a line or body cut off by `limit` as [`Error::TooLarge`].
+fn exceeds(error: socket::Error, part: &str, limit: usize) -> Error {
+    match error {
+        socket::Error::TooLarge(_) => {
+            Error::TooLarge(format!("{part} over {limit} bytes"))
+        }
+        error => Error::Socket(error),
+    }
+}
+
+/// # HTTP Limits
+///
+/// Bounds on a response read with [`Response::read`], so a malformed or
+/// hostile response can't exhaust memory. Responses exceeding them fail
+/// with [`Error::TooLarge`] and the connection is reset.
+///
+/// * `max_header_bytes` - Status line and headers, and every chunk size
+///   line and trailer section.
+/// * `max_headers` - Number of header lines.
+/// * `max_body_bytes` - Body after removing the chunk framing, and again
+///   after decompressing it.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    pub max_header_bytes: usize,
+    pub max_headers: usize,
+    pub max_body_bytes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_header_bytes: 64 * 1024,
+            max_headers: 100,
+            max_body_bytes: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// # HTTP Response
+///
+/// HTTP/1.1 response read from a [`Transport`]. The body is delimited by
+/// `Content-Length`, `Transfer-Encoding: chunked` or the peer closing the
+/// connection. `gzip` or `deflate` transfer and content codings are
+/// removed from the body, the headers are kept as received. The transport is
+/// disconnected after responses that don't keep the connection alive, and
+/// after malformed or oversized ones.
+///
+/// ```
+/// use kafka_bridge::http::{Limits, Response};
+/// use kafka_bridge::transport::MockTransport;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let mut transport = MockTransport::new();
+///     transport.push_response(
+///         "HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n[]",
+///     );
+///
+///     let response = Response::read(&mut transport, &Limits::default())
+///         .await
+///         .expect("response");
+///     assert_eq!(response.status, 200);
+///     assert_eq!(response.header("Content-Length"), Some("2"));
+///     assert_eq!(response.body, b"[]");
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Response {
+    pub version: String,
+    pub status: u16,
+    pub reason: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Reads the next response within `limits`, skipping up to 8 interim
+    /// `1xx` responses.
+    ///
+    /// # Errors
+    ///
+    /// * [`Error::Socket`] when reading from `transport` fails
+    /// * [`Error::Malformed`] on an invalid status line, header, chunk or
+    ///   compressed body, or an unsupported coding
+    /// * [`Error::TooLarge`] when the response exceeds `limits` or more
+    ///   interim responses precede it
+    pub async fn read<T: Transport>(
+        transport: &mut T,
+        limits: &Limits,
+    ) -> Result<Self, Error> {
+        let result = Self::read_limited(transport, limits).await;
+        match &result {
+            Ok(response) if response.keep_alive() => {}
+            Err(Error::Socket(_)) => {}
+
+            // Reset connections closed by the peer or left mid-response
+            _ => transport.disconnect().await,
+        }
+        result
+    }
+
+    async fn read_limited<T: Transport>(
+        transport: &mut T,
+        limits: &Limits,
+    ) -> Result<Self, Error> {
+        let max = limits.max_body_bytes;
+        for _ in 0..=MAX_INTERIM_RESPONSES {
+            let mut response = Self::read_head(transport, limits).await?;
+            if (100..200).contains(&response.status) {
+                continue;
+            }
+            let mut body = response.read_body(transport, limits).await?;
+            if let Some(encoding) = response.header("Transfer-Encoding") {
+                let encoding = unchunked(encoding);
+                body = decode("Transfer-Encoding", encoding, body, max)?;
+            }
+            if let Some(encoding) = response.header("Content-Encoding") {
+                body = decode("Content-Encoding", encoding, body, max)?;
+            }
+            response.body = body;
+            return Ok(response);
+        }
+        Err(Error::TooLarge(format!(
+            "More than {MAX_INTERIM_RESPONSES} interim responses"
+        )))
+    }
+
+    /// Value of the first `name` header, matched case-insensitively.
+    #[must_use]
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns `true` for `2xx` statuses.
+    #[must_use]
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Returns `true` when the connection stays open for the next request.
+    /// Responses whose body ends with the connection never keep it alive.
+    #[must_use]
+    pub fn keep_alive(&self) -> bool {
+        if !self.delimited() {
+            return false;
+        }
+        match self.header("Connection") {
+            Some(connection) if has_token(connection, "close") => false,
+            Some(connection) if has_token(connection, "keep-alive") => true,
+            _ => self.version != "HTTP/1.0",
+        }
+    }
+
+    /// Returns `true` when the body doesn't end with the connection.
+    fn delimited(&self) -> bool {
+        self.status == 204
+            || self.status == 304
+            || self.chunked()
+            || self.header("Content-Length").is_some()
+    }
+
+    fn chunked(&self) -> bool {
+        self.header("Transfer-Encoding")
+            .and_then(|encoding| encoding.rsplit(',').next())
+            .is_some_and(|encoding| {
+                encoding.trim().eq_ignore_ascii_case("chunked")
+            })
+    }
+
+    async fn read_head<T: Transport>(
+        transport: &mut T,
+        limits: &Limits,
+    ) -> Result<Self, Error> {
+        let max = limits.max_header_bytes;
+        let status_line = transport
+            .readln(max)
+            .await
+            .map_err(|error| exceeds(error, "Headers", max))?;
+        let mut parts = status_line.trim_end().splitn(3, ' ');
+        let version = parts.next().unwrap_or_default();
+        let status = parts
+            .next()
+            .filter(|status| status.len() == 3)
+            .and_then(|status| status.parse().ok());
+        let status = match status {
+            Some(status) if version.starts_with("HTTP/") => status,
+            _ => {
+                return Err(Error::Malformed(format!(
+                    "Invalid status line {status_line:?}"
+                )))
+            }
+        };
+
+        Ok(Self {
+            version: version.into(),
+            status,
+            reason: parts.next().unwrap_or_default().into(),
+            headers: read_headers(
+                transport,
+                shrink(max, &status_line, max)?,
+                limits,
+            )
+            .await?,
+            body: Vec::new(),
+        })
+    }
+
+    async fn read_body<T: Transport>(
+        &self,
+        transport: &mut T,
+        limits: &Limits,
+    ) -> Result<Vec<u8>, Error> {
+        let max = limits.max_body_bytes;
+        // Statuses without a body
+        if self.status == 204 || self.status == 304 {
+            return Ok(Vec::new());
+        }
+
+        if self.chunked() {
+            return read_chunked(transport, limits).await;
+        }
+
+        match self.header("Content-Length") {
+            Some(length) => {
+                let length = length.trim().parse().map_err(|_| {
+                    Error::Malformed(format!(
+                        "Invalid Content-Length {length:?}"
+                    ))
+                })?;
+                if length > max {
+                    return Err(Error::TooLarge(format!(
+                        "Content-Length {length} over {max} bytes"
+                    )));
+                }
+                Ok(transport.read_exact(length).await?)
+            }
+            None => transport
+                .read_to_end(max)
+                .await
+                .map_err(|error| exceeds(error, "Body", max)),
+        }
+    }
+}
+
+/// Returns `true` when the comma separated `value` lists `token`.
+fn has_token(value: &str, token: &str) -> bool {
+    value
+        .split(',')
+        .any(|item| item.trim().eq_ignore_ascii_case(token))
+}
+
+/// Transfer codings of `encoding` applied before the chunking, which
+/// [`read_chunked`] already removed.
+fn unchunked(encoding: &str) -> &str {
+    let (codings, last) = match encoding.rfind(',') {
+        Some(index) => (&encoding[..index], &encoding[index + 1..]),
+        None => ("", encoding),
+    };
+    if last.trim().eq_ignore_ascii_case("chunked") {
+        codings
+    } else {
+        encoding
+    }
+}
+
+/// Removes the codings listed in the `header` value `encoding` from
+/// `body`, the last applied first, failing once more than `max` bytes are
+/// decoded.
+fn decode(
+    header: &str,
+    encoding: &str,
+    mut body: Vec<u8>,
+    max: usize,
+) -> Result<Vec<u8>, Error> {
+    for coding in encoding.rsplit(',').map(str::trim) {
+        body = if coding.eq_ignore_ascii_case("gzip")
+            || coding.eq_ignore_ascii_case("x-gzip")
+        {
+            inflate(GzDecoder::new(&body[..]), max)?
+        } else if coding.eq_ignore_ascii_case("deflate") {
+            // Servers send deflate both zlib wrapped and raw
+            match inflate(ZlibDecoder::new(&body[..]), max) {
+                Err(Error::Malformed(_)) => {
+                    inflate(DeflateDecoder::new(&body[..]), max)?
+                }
+                result => result?,
+            }
+        } else if coding.is_empty() || coding.eq_ignore_ascii_case("identity")
+        {
+            continue;
+        } else {
+            return Err(Error::Malformed(format!(
+                "Unsupported {header} {coding:?}"
+            )));
+        };
+    }
+    Ok(body)
+}
+
+/// Reads `decoder` to the end, keeping at most `max` bytes.
+fn inflate(decoder: impl Read, max: usize) -> Result<Vec<u8>, Error> {
+    let limit =
+        u64::try_from(max).map_or(u64::MAX, |max| max.saturating_add(1));
+    let mut body = Vec::new();
+    decoder
+        .take(limit)
+        .read_to_end(&mut body)
+        .map_err(|error| {
+            Error::Malformed(format!("Invalid compressed body: {error}"))
+        })?;
+    if body.len() > max {
+        return Err(Error::TooLarge(format!(
+            "Decompressed body over {max} bytes"
+        )));
+    }
+    Ok(body)
+}
+
+/// Header `budget` left after `line`, which counts invalid UTF-8 bytes as
+/// the 3 byte replacement character and may outgrow the budget.
+fn shrink(budget: usize, line: &str, max: usize) -> Result<usize, Error> {
+    budget
+        .checked_sub(line.len())
+        .ok_or_else(|| Error::TooLarge(format!("Headers over {max} bytes")))
+}
+
+/// Reads header lines up to the empty line ending them, in at most
+/// `budget` bytes.
+async fn read_headers<T: Transport>(
+    transport: &mut T,
+    budget: usize,
+    limits: &Limits,
+) -> Result<Vec<(String, String)>, Error> {
+    let max = limits.max_header_bytes;
+    let mut remaining = budget;
+    let mut headers = Vec::new();
+    loop {
+        let line = transport
+            .readln(remaining)
+            .await
+            .map_err(|error| exceeds(error, "Headers", max))?;
+        remaining = shrink(remaining, &line, max)?;
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        if headers.len() == limits.max_headers {
+            return Err(Error::TooLarge(format!(
+                "More than {} headers",
+                limits.max_headers
+            )));
+        }
+        match line.find(':') {
+            Some(index) => headers.push((
+                line[..index].trim().into(),
+                line[index + 1..].trim().into(),
+            )),
+            None => {
+                return Err(Error::Malformed(format!(
+                    "Invalid header {line:?}"
+                )))
+            }
+        }
+    }
+}
+
+/// Reads a `Transfer-Encoding: chunked` body, discarding trailers.
+async fn read_chunked<T: Transport>(
+    transport: &mut T,
+    limits: &Limits,
+) -> Result<Vec<u8>, Error> {
+    let max = limits.max_header_bytes;
+    let mut body = Vec::new();
+    loop {
+        let line = transport
+            .readln(max)
+            .await
+            .map_err(|error| exceeds(error, "Chunk size", max))?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| {
+            Error::Malformed(format!("Invalid chunk size {line:?}"))
+        })?;
+        if size == 0 {
+            read_headers(transport, max, limits).await?;
+            return Ok(body);
+        }
+        if size > limits.max_body_bytes - body.len() {
+            return Err(Error::TooLarge(format!(
+                "Body over {} bytes",
+                limits.max_body_bytes
+            )));
+        }
+
+        body.extend(transport.read_exact(size).await?);
+        let terminator = match transport.readln(2).await {
+            Ok(terminator) => terminator,
+            Err(socket::Error::TooLarge(_)) => String::from("?"),
+            Err(error) => return Err(Error::Socket(error)),
+        };
+        if !terminator.trim_end().is_empty() {
+            return Err(Error::Malformed("Missing chunk terminator".into()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod http_tests {
+    use super::{Error, Limits, Response};
+    use crate::transport::MockTransport;
+    use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn compressed(encoding: &str, body: &[u8]) -> Vec<u8> {
+        let mut encoded = match encoding {
+            "gzip" => {
+                let mut encoder =
+                    GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body).expect("gzip");
+                encoder.finish().expect("gzip")
+            }
+            "zlib" => {
+                let mut encoder =
+                    ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body).expect("zlib");
+                encoder.finish().expect("zlib")
+            }
+            _ => {
+                let mut encoder =
+                    DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body).expect("deflate");
+                encoder.finish().expect("deflate")
+            }
+        };
+        let content_encoding = if encoding == "gzip" {
+            "gzip"
+        } else {
+            "deflate"
+        };
+        let mut response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Encoding: {content_encoding}\r\n\
+             Content-Length: {}\r\n\r\n",
+            encoded.len()
+        )
+        .into_bytes();
+        response.append(&mut encoded);
+        response
+    }
+
+    #[tokio::test]
+    async fn content_length_ok() {
+        let mut transport = MockTransport::new();
+        transport.push_response(
+            "HTTP/1.1 100 Continue\r\n\r\n\
+             HTTP/1.1 404 Not Found\r\nCONTENT-LENGTH: 9\r\n\
+             X-Empty:\r\n\r\nNot Found",
+        );
+
+        let response = Response::read(&mut transport, &Limits::default())
+            .await
+            .expect("response");
+        assert_eq!(response.status, 404);
+        assert_eq!(response.reason, "Not Found");
+        assert_eq!(response.header("x-empty"), Some(""));
+        assert_eq!(response.body, b"Not Found");
+        assert!(!response.is_success());
+        assert!(response.keep_alive());
+        assert_eq!(transport.disconnects(), 0);
+    }
+
+    #[tokio::test]
+    async fn chunked_ok() {
+        let mut transport = MockTransport::new();
+        transport.push_response(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: Chunked\r\n\
+             Content-Length: 1\r\n\r\n\
+             5;ext=1\r\nhello\r\n7\r\n, world\r\n0\r\nTrailer: x\r\n\r\n",
+        );
+
+        let response = Response::read(&mut transport, &Limits::default())
+            .await
+            .expect("response");
+        assert_eq!(response.body, b"hello, world");
+
+        // Compressed before the chunking
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"hello, world").expect("gzip");
+        let encoded = encoder.finish().expect("gzip");
+        let mut response = format!(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip, chunked\r\n\r\n\
+             {:x}\r\n",
+            encoded.len()
+        )
+        .into_bytes();
+        response.extend(&encoded);
+        response.extend(b"\r\n0\r\n\r\n");
+        let mut transport = MockTransport::new();
+        transport.push_response(response);
+        let response = Response::read(&mut transport, &Limits::default())
+            .await
+            .expect("response");
+        assert_eq!(response.body, b"hello, world");
+    }
+
+    #[tokio::test]
+    async fn compressed_ok() {
+        for encoding in &["gzip", "zlib", "raw"] {
+            let mut transport = MockTransport::new();
+            transport.push_response(compressed(encoding, b"[[],\"100\"]"));
+
+            let response = Response::read(&mut transport, &Limits::default())
+                .await
+                .expect("response");
+            assert_eq!(response.body, b"[[],\"100\"]", "{encoding}");
+            assert!(response.keep_alive());
+        }
+
+        let mut transport = MockTransport::new();
+        transport.push_response(
+            "HTTP/1.1 200 OK\r\nContent-Encoding: identity\r\n\
+             Content-Length: 5\r\n\r\nplain",
+        );
+        let response = Response::read(&mut transport, &Limits::default())
+            .await
+            .expect("response");
+        assert_eq!(response.body, b"plain");
+    }
+
+    #[tokio::test]
+    async fn compressed_error() {
+        for response in &[
+            "HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\n\
+             Content-Length: 5\r\n\r\nplain",
+            "HTTP/1.1 200 OK\r\nContent-Encoding: br\r\n\
+             Content-Length: 5\r\n\r\nplain",
+        ] {
+            let mut transport = MockTransport::new();
+            transport.push_response(response);
+            let result =
+                Response::read(&mut transport, &Limits::default()).await;
+            assert!(
+                matches!(result, Err(Error::Malformed(_))),
+                "{}",
+                response
+            );
+            assert_eq!(transport.disconnects(), 1);
+        }
+
+        // Small on the wire, over the limit once decompressed
+        let limits = Limits {
+            max_body_bytes: 1024,
+            ..Limits::default()
+        };
+        let mut transport = MockTransport::new();
+        transport.push_response(compressed("gzip", &[0; 4096]));
+        let result = Response::read(&mut transport, &limits).await;
+        assert!(matches!(result, Err(Error::TooLarge(_))));
+    }
+
+    #[tokio::test]
+    async fn close_delimited_ok() {
+        let mut transport = MockTransport::new();
+        transport.push_response(
+            "HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\nuntil close",
+        );
+
+        let response = Response::read(&mut transport, &Limits::default())
+            .await
+            .expect("response");
+        assert_eq!(response.body, b"until close");
+        assert!(!response.keep_alive());
+        assert_eq!(transport.disconnects(), 1);
+    }
+
+    #[tokio::test]
+    async fn connection_close_ok() {
+        let mut transport = MockTransport::new();
+        transport.push_response(
+            "HTTP/1.1 204 No Content\r\nConnection: Close\r\n\r\n",
+        );
+
+        let response = Response::read(&mut transport, &Limits::default())
+            .await
+            .expect("response");
+        assert!(response.body.is_empty());
+        assert_eq!(transport.disconnects(), 1);
+    }
+
+    #[tokio::test]
+    async fn malformed_error() {
+        for response in &[
+            "ICY 200 OK\r\n\r\n",
+            "HTTP/1.1 2000 OK\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nno colon\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: -1\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n",
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             1\r\nab\r\n",
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: br, chunked\r\n\r\n\
+             5\r\nhello\r\n0\r\n\r\n",
+        ] {
+            let mut transport = MockTransport::new();
+            transport.push_response(response);
+            let result =
+                Response::read(&mut transport, &Limits::default()).await;
+            assert!(
+                matches!(result, Err(Error::Malformed(_))),
+                "{}",
+                response
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn limits_error() {
+        let limits = Limits {
+            max_header_bytes: 64,
+            max_headers: 2,
+            max_body_bytes: 8,
+        };
+        for response in &[
+            "HTTP/1.1 200 OK\r\nX-Long: 0123456789012345678901234567890123456789\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 99999999999\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n",
+            "HTTP/1.0 200 OK\r\n\r\nclose delimited",
+            &"HTTP/1.1 100 Continue\r\n\r\n".repeat(9),
+        ] {
+            let mut transport = MockTransport::new();
+            transport.push_response(response);
+            let result = Response::read(&mut transport, &limits).await;
+            assert!(matches!(result, Err(Error::TooLarge(_))), "{}", response);
+            assert_eq!(transport.disconnects(), 1);
+        }
+
+        // Invalid UTF-8 grows when decoded, it can't overrun the budget
+        let mut response = b"HTTP/1.1 200 OK\r\nX-Bytes: ".to_vec();
+        response.extend(&[0xff; 36]);
+        response.extend(b"\r\n\r\n");
+        let mut transport = MockTransport::new();
+        transport.push_response(response);
+        let result = Response::read(&mut transport, &limits).await;
+        assert!(matches!(result, Err(Error::TooLarge(_))));
+
+        let mut transport = MockTransport::new();
+        transport.push_response(
+            "HTTP/1.1 200 OK\r\nA: 1\r\nContent-Length: 0\r\n\r\n\
+             HTTP/1.0 200 OK\r\n\r\n8 bytes!",
+        );
+        let response = Response::read(&mut transport, &limits).await;
+        assert!(response.expect("response").body.is_empty());
+        let response = Response::read(&mut transport, &limits).await;
+        assert_eq!(response.expect("response").body, b"8 bytes!");
+    }
+}
@@ -1,14 +1,18 @@
 #![deny(clippy::all)]
 #![deny(clippy::pedantic)]
 
+use crate::redact;
+use crate::timetoken::Timetoken;
 use futures_util::stream::StreamExt;
 use rdkafka::config::{ClientConfig, RDKafkaLogLevel};
 use rdkafka::consumer::stream_consumer::StreamConsumer;
 use rdkafka::consumer::{CommitMode, Consumer, DefaultConsumerContext};
-use rdkafka::error::KafkaResult;
+use rdkafka::error::{KafkaError, KafkaResult, RDKafkaError};
 use rdkafka::message::Message as RDKafkaMessage;
 use rdkafka::producer::{FutureProducer, FutureRecord};
 use rdkafka::util::Timeout;
+use std::convert::TryFrom;
+use std::fmt;
 use std::time::Duration;
 use tokio::sync::mpsc::Sender;
 
@@ -33,17 +37,82 @@ pub struct SubscribeClient {
     group: String,
 }
 
+/// Kafka client failure with the underlying [`KafkaError`].
 #[derive(Debug)]
 pub enum Error {
-    KafkaInitialize,
-    Publish,
-    PublishWrite,
-    PublishResponse,
-    Subscribe,
-    SubscribeWrite,
-    SubscribeRead,
-    MissingTopic,
-    HTTPResponse,
+    /// The client could not be created or subscribed.
+    KafkaInitialize(KafkaError),
+    /// Producing a message failed.
+    Produce(KafkaError),
+    /// Consuming or committing a message failed.
+    Consume(KafkaError),
+}
+
+impl Error {
+    /// Returns `true` when repeating the operation may succeed.
+    /// Configuration, authorization and oversized message failures are
+    /// not retryable.
+    #[must_use]
+    pub fn is_retryable(&self) -> bool {
+        let error = match self {
+            Error::KafkaInitialize(error)
+            | Error::Produce(error)
+            | Error::Consume(error) => error,
+        };
+        match error {
+            KafkaError::ClientConfig(..)
+            | KafkaError::ClientCreation(_)
+            | KafkaError::Nul(_)
+            | KafkaError::Subscription(_) => false,
+            KafkaError::MessageProduction(code)
+            | KafkaError::MessageConsumption(code)
+            | KafkaError::Global(code) => !matches!(
+                code,
+                RDKafkaError::InvalidMessage
+                    | RDKafkaError::InvalidMessageSize
+                    | RDKafkaError::MessageSizeTooLarge
+                    | RDKafkaError::InvalidArgument
+                    | RDKafkaError::Authentication
+                    | RDKafkaError::SaslAuthenticationFailed
+                    | RDKafkaError::TopicAuthorizationFailed
+                    | RDKafkaError::GroupAuthorizationFailed
+                    | RDKafkaError::ClusterAuthorizationFailed
+            ),
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::KafkaInitialize(error) => {
+                write!(f, "Kafka initialize failed: {error}")
+            }
+            Error::Produce(error) => {
+                write!(f, "Kafka produce failed: {error}")
+            }
+            Error::Consume(error) => {
+                write!(f, "Kafka consume failed: {error}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::KafkaInitialize(error)
+            | Error::Produce(error)
+            | Error::Consume(error) => Some(error),
+        }
+    }
+}
+
+/// Kafka record timestamp in milliseconds of the `timetoken`, or of now.
+fn timestamp(timetoken: Option<Timetoken>) -> i64 {
+    let millis = timetoken.unwrap_or_else(Timetoken::now).as_millis();
+    i64::try_from(millis).unwrap_or(i64::MAX)
 }
 
 #[cfg(feature = "sasl-plain")]
@@ -157,13 +226,13 @@ impl SubscribeClient {
             config.create_with_context(context);
 
         let consumer = consumer.map_err(|err| {
-            println!("Failed to intialize consumer: {}", err);
-            Error::KafkaInitialize
+            println!("Failed to intialize consumer: {err}");
+            Error::KafkaInitialize(err)
         })?;
 
         consumer.subscribe(&[topic]).map_err(|err| {
-            println!("Failed to initialize: {}", err);
-            Error::KafkaInitialize
+            println!("Failed to initialize: {err}");
+            Error::KafkaInitialize(err)
         })?;
 
         Ok(Self {
@@ -196,13 +265,13 @@ impl SubscribeClient {
         let consumer: KafkaResult<CustomConsumer> = config.create();
 
         let consumer = consumer.map_err(|err| {
-            println!("Failed to intialize consumer: {}", err);
-            Error::KafkaInitialize
+            println!("Failed to intialize consumer: {err}");
+            Error::KafkaInitialize(err)
         })?;
 
         consumer.subscribe(&[topic]).map_err(|err| {
-            println!("Failed to initialize: {}", err);
-            Error::KafkaInitialize
+            println!("Failed to initialize: {err}");
+            Error::KafkaInitialize(err)
         })?;
 
         Ok(Self {
@@ -216,20 +285,34 @@ impl SubscribeClient {
     ///
     /// # Errors
     ///
-    /// This function can return [`KafkaError`](rdkafka::error::KafkaError) on
-    /// unsuccessful poll.
-    pub async fn consume(&mut self) -> KafkaResult<()> {
+    /// This function can return [`Error::Consume`] on unsuccessful poll or
+    /// commit.
+    ///
+    /// # Panics
+    ///
+    /// Panics when the receiving end of the channel has been dropped.
+    pub async fn consume(&mut self) -> Result<(), Error> {
         let mut message_stream = self.consumer.start();
         while let Some(r) = message_stream.next().await {
-            let m = r?;
+            let m = r.map_err(Error::Consume)?;
 
             let mut data = match m.payload_view::<str>() {
                 None => String::new(),
                 Some(Ok(s)) => s.into(),
                 Some(Err(_e)) => String::new(),
             };
-            println!("{{\"info\": \"Received message from Kafka Broker.\", \"key\": \"{:?}\", \"payload\": {}, \"topic\": {}, \"partition\": {}, \"offset\": {}, \"timestamp\": {:?}}}",
-                  m.key(), data, m.topic(), m.partition(), m.offset(), m.timestamp());
+            println!(
+                "{}",
+                json::stringify(json::object! {
+                    "info" => "Received message from Kafka Broker.",
+                    "key" => m.key().map(|key| redact::payload(&String::from_utf8_lossy(key))),
+                    "payload" => redact::payload(&data),
+                    "topic" => m.topic(),
+                    "partition" => m.partition(),
+                    "offset" => m.offset(),
+                    "timestamp" => m.timestamp().to_millis(),
+                })
+            );
 
             let parsetest = json::parse(&data);
             if parsetest.is_err() {
@@ -240,13 +323,15 @@ impl SubscribeClient {
                 .send(Message {
                     topic: self.topic.clone(),
                     group: self.group.clone(),
-                    data: data.to_string(),
+                    data: data.clone(),
                 })
                 .await
                 .map_err(|_err| ())
                 .expect("Error writing to mpsc Sender");
 
-            self.consumer.commit_message(&m, CommitMode::Async)?;
+            self.consumer
+                .commit_message(&m, CommitMode::Async)
+                .map_err(Error::Consume)?;
         }
 
         Ok(())
@@ -290,7 +375,7 @@ impl SubscribeClient {
 ///     let message: kafka_bridge::pubnub::Message =
 ///         kafka_message_rx.recv().expect("MPSC Channel Receiver");
 ///
-///     let _result = kafka.produce(&message.data);
+///     let _result = kafka.produce(&message.data, Some(message.timetoken));
 /// }
 /// ```
 // =-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=
@@ -309,8 +394,8 @@ impl PublishClient {
             .create();
 
         let producer = producer.map_err(|err| {
-            println!("Failed to init kafka producer: {}", err);
-            Error::KafkaInitialize
+            println!("Failed to init kafka producer: {err}");
+            Error::KafkaInitialize(err)
         })?;
 
         Ok(Self {
@@ -339,8 +424,8 @@ impl PublishClient {
                 .create();
 
         let producer = producer.map_err(|err| {
-            println!("Failed to init kafka producer: {}", err);
-            Error::KafkaInitialize
+            println!("Failed to init kafka producer: {err}");
+            Error::KafkaInitialize(err)
         })?;
 
         Ok(Self {
@@ -349,20 +434,53 @@ impl PublishClient {
         })
     }
 
-    /// Sends `message` into Kafka.
+    /// Sends `message` into Kafka, timestamped with the `PubNub` publish
+    /// `timetoken` when one is given and the produce time otherwise.
     ///
     /// # Errors
     ///
-    /// This function can return [`KafkaError`](rdkafka::error::KafkaError) on
-    /// unsuccessful send.
-    pub async fn produce(&mut self, message: &str) -> KafkaResult<()> {
+    /// This function can return [`Error::Produce`] on unsuccessful send.
+    pub async fn produce(
+        &mut self,
+        message: &str,
+        timetoken: Option<Timetoken>,
+    ) -> Result<(), Error> {
         self.producer
             .send(
-                FutureRecord::<'_, (), _>::to(&self.topic).payload(message),
-                Timeout::After(Duration::from_millis(5 * 1000)),
+                FutureRecord::<'_, (), _>::to(&self.topic)
+                    .payload(message)
+                    .timestamp(timestamp(timetoken)),
+                Timeout::After(Duration::from_secs(5)),
             )
             .await
             .map(|_| ())
-            .map_err(|(err, _)| err)
+            .map_err(|(err, _)| Error::Produce(err))
+    }
+
+    /// Sends `message` into `topic` under `key`, `None` sends a tombstone
+    /// deleting the key from compacted topics. Timestamped like
+    /// [`PublishClient::produce`].
+    ///
+    /// # Errors
+    ///
+    /// This function can return [`Error::Produce`] on unsuccessful send.
+    pub async fn produce_keyed(
+        &mut self,
+        topic: &str,
+        key: &str,
+        message: Option<&str>,
+        timetoken: Option<Timetoken>,
+    ) -> Result<(), Error> {
+        let mut record = FutureRecord::<'_, _, str>::to(topic)
+            .key(key)
+            .timestamp(timestamp(timetoken));
+        if let Some(message) = message {
+            record = record.payload(message);
+        }
+        self.producer
+            .send(record, Timeout::After(Duration::from_secs(5)))
+            .await
+            .map(|_| ())
+            .map_err(|(err, _)| Error::Produce(err))
     }
 }
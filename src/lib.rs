@@ -3,6 +3,13 @@
 #![deny(clippy::all)]
 #![deny(clippy::pedantic)]
 
+pub mod checkpoint;
+pub mod crypto;
+pub mod http;
 pub mod kafka;
+pub mod proxy;
 pub mod pubnub;
+pub mod redact;
 pub mod socket;
+pub mod timetoken;
+pub mod transport;
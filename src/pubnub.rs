@@ -1,83 +1,701 @@
-use crate::socket::Socket;
+use crate::crypto::{self, Cryptor};
+use crate::http::{self, Limits, Response};
+use crate::proxy::Proxy;
+use crate::socket::{self, ReconnectPolicy, Socket, TlsConfig};
+use crate::timetoken::Timetoken;
+use crate::transport::Transport;
 use json::JsonValue;
-use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub struct SubscribeClient {
-    socket: Socket,
+/// Filter keeping the bridge from receiving its own messages.
+const ECHO_FILTER: &str = "source != 'KAFKA'";
+
+/// Meta of messages published by the bridge, matched by [`ECHO_FILTER`].
+const ECHO_META: &str = r#"{"source":"KAFKA"}"#;
+
+/// Most messages of one channel returned by a history request.
+const HISTORY_PAGE: usize = 100;
+
+/// Suffix of the channels carrying the presence events of a channel.
+const PRESENCE_SUFFIX: &str = "-pnpres";
+
+pub struct SubscribeClient<T: Transport = Socket> {
+    transport: T,
+    host: String,
     root: String,
-    channel: String,
-    messages: Vec<Message>,
-    timetoken: String,
+    channels: Vec<String>,
+    channel_groups: Vec<String>,
+    events: VecDeque<Event>,
+    timetoken: Timetoken,
+    subscribed: bool,
     subscribe_key: String,
-    _secret_key: String,
+    publish_key: String,
+    secret_key: String,
+    auth_key: Option<AuthKey>,
+    cryptor: Option<Cryptor>,
+    filter: String,
+    presence: bool,
+    backfill: bool,
+    backfill_from: Option<Timetoken>,
+    uuid: String,
+    heartbeat: Option<u32>,
+    region: String,
     agent: String,
+    limits: Limits,
 }
 
-pub struct PublishClient {
-    socket: Socket,
+pub struct PublishClient<T: Transport = Socket> {
+    transport: T,
+    host: String,
     root: String,
     publish_key: String,
     subscribe_key: String,
-    _secret_key: String,
+    secret_key: String,
+    auth_key: Option<AuthKey>,
+    cryptor: Option<Cryptor>,
+    meta: String,
+    uuid: String,
     agent: String,
+    limits: Limits,
 }
 
+/// Message received by a [`SubscribeClient`]. `subscription` is the
+/// channel, wildcard or channel group it matched, channels without the
+/// root. `region` is the data center the message was published in and
+/// `publisher` the UUID of the publishing client, empty when it sent
+/// none.
+#[derive(Debug)]
 pub struct Message {
     pub root: String,
     pub channel: String,
+    pub subscription: String,
     pub data: String,
     pub metadata: String,
-    pub id: String,
+    pub timetoken: Timetoken,
+    pub region: u32,
+    pub publisher: String,
+    pub message_type: MessageType,
+    pub sequence: u64,
+    pub flags: u64,
+}
+
+/// Kind of a [`Message`], from the `e` field of the subscribe envelope.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageType {
+    Message,
+    Signal,
+    Object,
+    MessageAction,
+    File,
+    Other(u64),
 }
 
+impl From<u64> for MessageType {
+    fn from(value: u64) -> Self {
+        match value {
+            0 => MessageType::Message,
+            1 => MessageType::Signal,
+            2 => MessageType::Object,
+            3 => MessageType::MessageAction,
+            4 => MessageType::File,
+            other => MessageType::Other(other),
+        }
+    }
+}
+
+/// Presence event received by a [`SubscribeClient`] with presence
+/// enabled. `action` is `join`, `leave`, `timeout`, `state-change` or
+/// `interval`, the latter without `uuid` when it only updates the
+/// `occupancy`. `state` is the JSON state of the `uuid`, `null` without
+/// one.
+#[derive(Debug)]
+pub struct PresenceEvent {
+    pub root: String,
+    pub channel: String,
+    pub subscription: String,
+    pub action: String,
+    pub uuid: String,
+    pub occupancy: u64,
+    pub timestamp: u64,
+    pub state: String,
+    pub timetoken: Timetoken,
+}
+
+impl PresenceEvent {
+    /// JSON object of the event, as produced into Kafka.
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        let state = json::parse(&self.state).unwrap_or(JsonValue::Null);
+        json::stringify(json::object! {
+            "channel" => self.channel.as_str(),
+            "action" => self.action.as_str(),
+            "uuid" => self.uuid.as_str(),
+            "occupancy" => self.occupancy,
+            "timestamp" => self.timestamp,
+            "state" => state,
+        })
+    }
+}
+
+/// Message or presence event received by a [`SubscribeClient`].
+#[derive(Debug)]
+pub enum Event {
+    Message(Message),
+    Presence(PresenceEvent),
+    /// Message the cryptor failed to decrypt, `data` kept as received.
+    Undecryptable(Message, crypto::Error),
+}
+
+/// # Access Manager Auth Key
+///
+/// Auth key or token sent with every request to keysets with Access
+/// Manager enabled. A key read from a file is read again before every
+/// request, so the file can be rotated while the bridge runs.
+#[derive(Clone, Debug)]
+pub enum AuthKey {
+    Key(String),
+    File(PathBuf),
+}
+
+impl AuthKey {
+    /// Current value of the key, surrounding whitespace removed.
+    fn value(&self) -> Result<String, Error> {
+        match self {
+            AuthKey::Key(key) => Ok(key.clone()),
+            AuthKey::File(path) => std::fs::read_to_string(path)
+                .map(|key| key.trim().into())
+                .map_err(Error::AuthKey),
+        }
+    }
+}
+
+/// # Publish Options
+///
+/// Per message options of a [`PublishClient::publish_with_options`]
+/// call. `store` overrides whether the keyset's Message Persistence keeps
+/// the message, for `ttl` hours when set. `norep` keeps the message in
+/// the data center it was published in and `custom_message_type` labels
+/// it for subscribers.
+///
+/// Parsed from a JSON object with the same fields, `"fire": true` sets
+/// both `store` to `false` and `norep`:
+///
+/// ```
+/// use kafka_bridge::pubnub::PublishOptions;
+///
+/// let options: PublishOptions =
+///     r#"{"fire":true,"custom_message_type":"telemetry"}"#
+///         .parse()
+///         .expect("options");
+/// assert_eq!(options, PublishOptions {
+///     custom_message_type: Some("telemetry".into()),
+///     ..PublishOptions::fire()
+/// });
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PublishOptions {
+    pub store: Option<bool>,
+    pub ttl: Option<u32>,
+    pub norep: bool,
+    pub custom_message_type: Option<String>,
+}
+
+impl PublishOptions {
+    /// Options of messages that are neither stored nor replicated.
+    #[must_use]
+    pub fn fire() -> Self {
+        Self {
+            store: Some(false),
+            norep: true,
+            ..Self::default()
+        }
+    }
+
+    /// Checks the custom message type is 3 to 50 letters, digits, `-` or
+    /// `_`, starting and ending with a letter or digit.
+    fn validate(&self) -> Result<(), Error> {
+        let message_type = self.custom_message_type.as_deref().unwrap_or("");
+        let valid = self.custom_message_type.is_none()
+            || (3..=50).contains(&message_type.len())
+                && message_type.bytes().all(|byte| {
+                    byte.is_ascii_alphanumeric()
+                        || byte == b'-'
+                        || byte == b'_'
+                })
+                && message_type
+                    .starts_with(|c: char| c.is_ascii_alphanumeric())
+                && message_type
+                    .ends_with(|c: char| c.is_ascii_alphanumeric());
+        if valid {
+            Ok(())
+        } else {
+            Err(Error::InvalidPublishOptions(format!(
+                "Invalid custom message type {message_type:?}"
+            )))
+        }
+    }
+
+    /// Query parameters of the options, none for the defaults.
+    fn parameters(&self) -> Vec<(&'static str, String)> {
+        let mut parameters = Vec::new();
+        if let Some(store) = self.store {
+            parameters.push(("store", if store { "1" } else { "0" }.into()));
+        }
+        if let Some(ttl) = self.ttl {
+            parameters.push(("ttl", ttl.to_string()));
+        }
+        if self.norep {
+            parameters.push(("norep", "true".into()));
+        }
+        if let Some(message_type) = &self.custom_message_type {
+            parameters.push(("custom_message_type", message_type.clone()));
+        }
+        parameters
+    }
+}
+
+impl FromStr for PublishOptions {
+    type Err = Error;
+
+    fn from_str(options: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            |cause: &str| Error::InvalidPublishOptions(cause.into());
+        let parsed = json::parse(options).map_err(|error| {
+            Error::InvalidPublishOptions(format!("{error}"))
+        })?;
+        if !parsed.is_object() {
+            return Err(invalid("Not a JSON object"));
+        }
+        let mut options = Self::default();
+        for (name, value) in parsed.entries() {
+            match name {
+                "store" => {
+                    options.store = Some(
+                        value.as_bool().ok_or_else(|| invalid("store"))?,
+                    );
+                }
+                "ttl" => {
+                    options.ttl =
+                        Some(value.as_u32().ok_or_else(|| invalid("ttl"))?);
+                }
+                "norep" => {
+                    options.norep =
+                        value.as_bool().ok_or_else(|| invalid("norep"))?;
+                }
+                "fire" => {
+                    if value.as_bool().ok_or_else(|| invalid("fire"))? {
+                        options.store = Some(false);
+                        options.norep = true;
+                    }
+                }
+                "custom_message_type" => {
+                    options.custom_message_type = Some(
+                        value
+                            .as_str()
+                            .ok_or_else(|| invalid("custom_message_type"))?
+                            .into(),
+                    );
+                }
+                _ => {
+                    return Err(Error::InvalidPublishOptions(format!(
+                        "Unknown option {name:?}"
+                    )))
+                }
+            }
+        }
+        options.validate()?;
+        Ok(options)
+    }
+}
+
+/// `PubNub` client failure with its cause.
 #[derive(Debug)]
 pub enum Error {
-    Initialize,
-    Publish,
-    PublishWrite,
-    PublishResponse,
-    Subscribe,
-    SubscribeWrite,
-    SubscribeRead,
+    /// No channel or channel group to subscribe to was given.
     MissingChannel,
-    HTTPResponse,
+    /// The request could not be sent or the response could not be read.
+    Socket(socket::Error),
+    /// `PubNub` answered with a non-2xx status.
+    HTTPStatus { status: u16, body: String },
+    /// Access Manager denied the request with a `403`, the auth key lacks
+    /// the permission or has expired.
+    AccessDenied(String),
+    /// The auth key file could not be read.
+    AuthKey(io::Error),
+    /// The HTTP response was malformed.
+    HTTPResponse(String),
+    /// The HTTP response exceeded the [`Limits`], the connection is reset.
+    ResponseTooLarge(String),
+    /// The response body was not the expected JSON.
+    Parse(String),
+    /// The request could not be signed with the secret key.
+    Signature(ErrorStack),
+    /// The message could not be encrypted with the cipher key.
+    Crypto(crypto::Error),
+    /// The filter expression is empty or unbalanced.
+    InvalidFilter(String),
+    /// The publish meta is not a JSON object.
+    InvalidMeta(String),
+    /// The publish options are malformed or invalid.
+    InvalidPublishOptions(String),
+}
+
+impl Error {
+    /// Returns `true` when repeating the request may succeed: transport
+    /// failures, malformed HTTP or JSON responses, such as ones garbled or
+    /// cut short by a proxy, `408`, `429` and `5xx` statuses.
+    /// Responses over the [`Limits`] are not retryable, they stay too
+    /// large until the limits change. An unreadable auth key file fails
+    /// before any request is sent, it is not retryable so callers back
+    /// off instead of spinning.
+    #[must_use]
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::MissingChannel
+            | Error::ResponseTooLarge(_)
+            | Error::AccessDenied(_)
+            | Error::AuthKey(_)
+            | Error::Signature(_)
+            | Error::Crypto(_)
+            | Error::InvalidFilter(_)
+            | Error::InvalidMeta(_)
+            | Error::InvalidPublishOptions(_) => false,
+            Error::Socket(error) => error.is_retryable(),
+            Error::HTTPStatus { status, .. } => {
+                *status == 408 || *status == 429 || *status >= 500
+            }
+            Error::HTTPResponse(_) | Error::Parse(_) => true,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::MissingChannel => write!(f, "Missing channel"),
+            Error::Socket(error) => write!(f, "{error}"),
+            Error::HTTPStatus { status, body } => {
+                write!(f, "HTTP status {status}: {body}")
+            }
+            Error::AccessDenied(body) => write!(f, "Access denied: {body}"),
+            Error::AuthKey(error) => {
+                write!(f, "Unreadable auth key file: {error}")
+            }
+            Error::HTTPResponse(cause) => {
+                write!(f, "Malformed HTTP response: {cause}")
+            }
+            Error::ResponseTooLarge(cause) => {
+                write!(f, "HTTP response too large: {cause}")
+            }
+            Error::Parse(cause) => write!(f, "Malformed JSON: {cause}"),
+            Error::Signature(error) => {
+                write!(f, "Request signing failed: {error}")
+            }
+            Error::Crypto(error) => write!(f, "{error}"),
+            Error::InvalidFilter(cause) => {
+                write!(f, "Invalid filter expression: {cause}")
+            }
+            Error::InvalidMeta(cause) => write!(f, "Invalid meta: {cause}"),
+            Error::InvalidPublishOptions(cause) => {
+                write!(f, "Invalid publish options: {cause}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Socket(error) => Some(error),
+            Error::AuthKey(error) => Some(error),
+            Error::Signature(error) => Some(error),
+            Error::Crypto(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<socket::Error> for Error {
+    fn from(error: socket::Error) -> Self {
+        Error::Socket(error)
+    }
+}
+
+impl From<http::Error> for Error {
+    fn from(error: http::Error) -> Self {
+        match error {
+            http::Error::Socket(error) => Error::Socket(error),
+            http::Error::Malformed(cause) => Error::HTTPResponse(cause),
+            http::Error::TooLarge(cause) => Error::ResponseTooLarge(cause),
+        }
+    }
 }
 
 // =-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=
-// HTTP Response Reader/Parser
+// Origin Connection
 // =-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=
-fn http_response(socket: &mut Socket) -> Result<JsonValue, Error> {
-    let mut body_length: usize = 0;
-    loop {
-        let data = match socket.readln() {
-            Ok(data) => data,
-            Err(_error) => return Err(Error::HTTPResponse),
-        };
+/// Parses a `PubNub` origin into `host:port` and whether it uses TLS.
+///
+/// * `https://ps.pndsn.com` and `ps.pndsn.com:443` connect with TLS.
+/// * `http://ps.pndsn.com` and `ps.pndsn.com:80` connect in cleartext.
+fn origin(host: &str) -> (String, bool) {
+    let (address, tls) = if let Some(address) = host.strip_prefix("https://")
+    {
+        (address, true)
+    } else if let Some(address) = host.strip_prefix("http://") {
+        (address, false)
+    } else {
+        (host, host.ends_with(":443"))
+    };
+    let address = address.trim_end_matches('/');
+    if address.contains(':') {
+        (address.into(), tls)
+    } else {
+        let port = if tls { 443 } else { 80 };
+        (format!("{address}:{port}"), tls)
+    }
+}
 
-        // Capture Content Length of Payload
-        if body_length == 0 && data.contains("Content-Length") {
-            let result = match data.split_whitespace().nth(1) {
-                Some(length) => length.parse(),
-                None => return Err(Error::HTTPResponse),
-            };
-            body_length = match result {
-                Ok(length) => length,
-                Err(_error) => return Err(Error::HTTPResponse),
-            };
+/// Opens the origin socket, tunneled through the proxy configured with
+/// `HTTPS_PROXY`, `HTTP_PROXY`, `ALL_PROXY` and `NO_PROXY`.
+fn connect(host: &str, agent: &str, timeout: u64, tls: &TlsConfig) -> Socket {
+    let (address, encrypted) = origin(host);
+    let mut socket = if encrypted {
+        Socket::new_with_tls(&address, agent, timeout, tls)
+    } else {
+        Socket::new(&address, agent, timeout)
+    };
+    socket.set_proxy(Proxy::from_env(&address, encrypted));
+    socket
+}
+
+/// Value for the `Host` request header.
+fn host_header(host: &str) -> String {
+    let (address, tls) = origin(host);
+    let default = if tls { ":443" } else { ":80" };
+    address.strip_suffix(default).unwrap_or(&address).into()
+}
+
+// =-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=
+// Request Signing
+// =-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=
+/// Characters escaped in query values, the encoding `PubNub` checks
+/// signatures against.
+const QUERY: &AsciiSet =
+    &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_');
+
+/// Characters escaped in subscribed channel names, keeping `*` wildcards.
+const CHANNEL: &AsciiSet = &QUERY.remove(b'*');
+
+/// Builds the sorted query string of a `method` request to `path`, with
+/// the `auth_key` when one is set and signed when a `secret_key` is set.
+fn query(
+    secret_key: &str,
+    publish_key: &str,
+    auth_key: Option<&AuthKey>,
+    method: &str,
+    path: &str,
+    mut parameters: Vec<(&str, String)>,
+    body: &str,
+) -> Result<String, Error> {
+    if let Some(auth_key) = auth_key {
+        parameters.push(("auth", auth_key.value()?));
+    }
+    if secret_key.is_empty() {
+        return Ok(encode_query(parameters));
+    }
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    signed_query(
+        secret_key,
+        publish_key,
+        method,
+        path,
+        parameters,
+        body,
+        timestamp,
+    )
+}
+
+/// Adds `timestamp` and a `PubNub` v2 signature to the query: an
+/// HMAC-SHA256 of the method, publish key, path, sorted query and body,
+/// base64url encoded without padding.
+fn signed_query(
+    secret_key: &str,
+    publish_key: &str,
+    method: &str,
+    path: &str,
+    mut parameters: Vec<(&str, String)>,
+    body: &str,
+    timestamp: u64,
+) -> Result<String, Error> {
+    parameters.push(("timestamp", timestamp.to_string()));
+    let query = encode_query(parameters);
+    let plain = format!("{method}\n{publish_key}\n{path}\n{query}\n{body}");
+
+    let key = PKey::hmac(secret_key.as_bytes()).map_err(Error::Signature)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)
+        .map_err(Error::Signature)?;
+    signer.update(plain.as_bytes()).map_err(Error::Signature)?;
+    let digest = signer.sign_to_vec().map_err(Error::Signature)?;
+    let signature = openssl::base64::encode_block(&digest)
+        .replace('+', "-")
+        .replace('/', "_");
+    let signature = signature.trim_end_matches('=');
+    Ok(format!("{query}&signature=v2.{signature}"))
+}
+
+/// Parameters identifying the client on every request, the `uuid` when
+/// one is set.
+fn client_parameters(agent: &str, uuid: &str) -> Vec<(&'static str, String)> {
+    let mut parameters = vec![("pnsdk", agent.into())];
+    if !uuid.is_empty() {
+        parameters.push(("uuid", uuid.into()));
+    }
+    parameters
+}
+
+/// Encodes `parameters` sorted by name.
+fn encode_query(mut parameters: Vec<(&str, String)>) -> String {
+    parameters.sort();
+    parameters
+        .iter()
+        .map(|(name, value)| {
+            format!("{name}={}", utf8_percent_encode(value, QUERY))
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Unsigned integer of a JSON number or string, `0` when absent or
+/// malformed.
+fn number(value: &JsonValue) -> u64 {
+    match value.as_str() {
+        Some(value) => value.parse().unwrap_or(0),
+        None => value.as_u64().unwrap_or(0),
+    }
+}
+
+/// Message `data` decrypted with the `cryptor`, or as received without
+/// one or when it isn't a string, as unencrypted JSON messages aren't.
+fn decrypt(
+    cryptor: Option<&Cryptor>,
+    data: &JsonValue,
+) -> Result<String, crypto::Error> {
+    let decrypted = match (cryptor, data.as_str()) {
+        (Some(cryptor), Some(encrypted)) => cryptor.decrypt(encrypted)?,
+        _ => return Ok(data.to_string()),
+    };
+    let plaintext = String::from_utf8(decrypted).map_err(|_| {
+        crypto::Error::Malformed("Plaintext is not UTF-8".into())
+    })?;
+    Ok(match json::parse(&plaintext) {
+        Ok(value) => value.to_string(),
+        Err(_) => plaintext,
+    })
+}
+
+/// Logs the messages published after `from` and up to `to` that backfill
+/// failed to recover, with the `cause`.
+fn log_gap(from: Timetoken, to: Timetoken, cause: impl fmt::Display) {
+    println!(
+        "{}",
+        json::stringify(json::object! {
+            "error" => format!("Backfill skipped: {cause}"),
+            "from" => from.to_string(),
+            "to" => to.to_string(),
+        })
+    );
+}
+
+/// Event of a received `message`, decrypting its `data` with the
+/// `cryptor` or keeping it as received when that fails.
+fn message_event(
+    cryptor: Option<&Cryptor>,
+    mut message: Message,
+    data: &JsonValue,
+) -> Event {
+    match decrypt(cryptor, data) {
+        Ok(plaintext) => {
+            message.data = plaintext;
+            Event::Message(message)
+        }
+        Err(error) => {
+            message.data = data.to_string();
+            Event::Undecryptable(message, error)
         }
+    }
+}
 
-        // End of Headers
-        if data.len() == 2 {
-            let paylaod = match socket.read(body_length) {
-                Ok(data) => data,
-                Err(_error) => return Err(Error::HTTPResponse),
-            };
-            match json::parse(&paylaod) {
-                Ok(response) => return Ok(response),
-                Err(_error) => return Err(Error::HTTPResponse),
-            };
+/// Checks `expression` is not empty and its quotes and parentheses are
+/// balanced, so it can't cancel the echo filter it's combined with.
+fn validate_filter(expression: &str) -> Result<(), Error> {
+    if expression.trim().is_empty() {
+        return Err(Error::InvalidFilter("Empty expression".into()));
+    }
+    let mut depth = 0_usize;
+    let mut quote = None;
+    for character in expression.chars() {
+        match (quote, character) {
+            (_, character) if character.is_control() => {
+                return Err(Error::InvalidFilter(
+                    "Control character in expression".into(),
+                ))
+            }
+            (Some(open), character) if character == open => quote = None,
+            (None, '\'' | '"') => quote = Some(character),
+            (None, '(') => depth += 1,
+            (None, ')') => {
+                depth = depth.checked_sub(1).ok_or_else(|| {
+                    Error::InvalidFilter("Unbalanced parentheses".into())
+                })?;
+            }
+            _ => {}
         }
     }
+    if quote.is_some() {
+        return Err(Error::InvalidFilter("Unterminated string".into()));
+    }
+    if depth > 0 {
+        return Err(Error::InvalidFilter("Unbalanced parentheses".into()));
+    }
+    Ok(())
+}
+
+// =-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=
+// HTTP Response Reader/Parser
+// =-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=
+async fn http_response<T: Transport>(
+    transport: &mut T,
+    limits: &Limits,
+) -> Result<JsonValue, Error> {
+    let response = Response::read(transport, limits).await?;
+    if response.status == 403 {
+        return Err(Error::AccessDenied(
+            String::from_utf8_lossy(&response.body).into_owned(),
+        ));
+    }
+    if !response.is_success() {
+        return Err(Error::HTTPStatus {
+            status: response.status,
+            body: String::from_utf8_lossy(&response.body).into_owned(),
+        });
+    }
+    let body = std::str::from_utf8(&response.body)
+        .map_err(|error| Error::Parse(format!("{error}")))?;
+    json::parse(body).map_err(|error| Error::Parse(format!("{error}")))
 }
 
 // =-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=
@@ -88,34 +706,44 @@ fn http_response(socket: &mut Socket) -> Result<JsonValue, Error> {
 /// ```no_run
 /// use kafka_bridge::pubnub::SubscribeClient;
 ///
-/// let host = "psdsn.pubnub.com:80";
-/// let channel = "demo";
-/// let root = "";
-/// let publish_key = "demo";
-/// let subscribe_key = "demo";
-/// let _secret_key = "secret";
-/// let agent = "kafka-bridge";
-/// let mut pubnub = SubscribeClient::new(
-///     host,
-///     root,
-///     channel,
-///     subscribe_key,
-///     _secret_key,
-///     agent,
-///  ).expect("KAFKA Subscribe Client");
+/// #[tokio::main]
+/// async fn main() {
+///     let host = "https://psdsn.pubnub.com";
+///     let channel = "demo";
+///     let root = "";
+///     let subscribe_key = "demo";
+///     let secret_key = "secret";
+///     let agent = "kafka-bridge";
+///     let mut pubnub = SubscribeClient::new(
+///         host,
+///         root,
+///         channel,
+///         subscribe_key,
+///         secret_key,
+///         agent,
+///     ).expect("KAFKA Subscribe Client");
 ///
-/// let result = pubnub.next_message();
-/// assert!(result.is_ok());
-/// let message = result.expect("Received Message");
-/// println!("{} -> {}", message.channel, message.data);
+///     let result = pubnub.next_message().await;
+///     assert!(result.is_ok());
+///     let message = result.expect("Received Message");
+///     println!("{} -> {}", message.channel, message.data);
+/// }
 /// ```
 // =-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=
 impl SubscribeClient {
     /// Creates a new [`SubscribeClient`].
+    /// The subscription starts with the first call to `next_message`.
+    ///
+    /// The `host` origin selects the transport, `https://` origins and
+    /// port `443` are encrypted with TLS.
     ///
     /// # Errors
     ///
-    /// This function can return [`Error::Subscribe`] on unsuccessful subscribe.
+    /// Never fails, `channel` may be empty when subscribing to channel
+    /// groups only. Without either, [`next_event`] returns
+    /// [`Error::MissingChannel`].
+    ///
+    /// [`next_event`]: SubscribeClient::next_event
     pub fn new(
         host: &str,
         root: &str,
@@ -124,106 +752,717 @@ impl SubscribeClient {
         secret_key: &str,
         agent: &str,
     ) -> Result<Self, Error> {
-        let socket = Socket::new(host, agent, 30);
+        Self::new_with_tls(
+            host,
+            root,
+            channel,
+            subscribe_key,
+            secret_key,
+            agent,
+            &TlsConfig::default(),
+        )
+    }
 
-        let mut pubnub = Self {
+    /// Creates a new [`SubscribeClient`] using `tls` to verify `https://`
+    /// origins.
+    ///
+    /// # Errors
+    ///
+    /// Never fails, `channel` may be empty when subscribing to channel
+    /// groups only. Without either, [`next_event`] returns
+    /// [`Error::MissingChannel`].
+    ///
+    /// [`next_event`]: SubscribeClient::next_event
+    pub fn new_with_tls(
+        host: &str,
+        root: &str,
+        channel: &str,
+        subscribe_key: &str,
+        secret_key: &str,
+        agent: &str,
+        tls: &TlsConfig,
+    ) -> Result<Self, Error> {
+        let socket = connect(host, agent, 30, tls);
+        SubscribeClient::with_transport(
             socket,
+            host,
+            root,
+            channel,
+            subscribe_key,
+            secret_key,
+            agent,
+        )
+    }
+
+    /// Replaces the [`ReconnectPolicy`] used when the connection to
+    /// `PubNub` is lost.
+    pub fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+        self.transport.set_reconnect_policy(policy);
+    }
+
+    /// Replaces the [`Proxy`] taken from the environment, `None`
+    /// connects directly.
+    pub fn set_proxy(&mut self, proxy: Option<Proxy>) {
+        self.transport.set_proxy(proxy);
+    }
+}
+
+impl<T: Transport> SubscribeClient<T> {
+    /// Creates a new [`SubscribeClient`] sending its requests through
+    /// `transport`, `host` only fills in the `Host` header.
+    ///
+    /// # Errors
+    ///
+    /// Never fails, `channel` may be empty when subscribing to channel
+    /// groups only. Without either, [`next_event`] returns
+    /// [`Error::MissingChannel`].
+    ///
+    /// [`next_event`]: SubscribeClient::next_event
+    pub fn with_transport(
+        transport: T,
+        host: &str,
+        root: &str,
+        channel: &str,
+        subscribe_key: &str,
+        secret_key: &str,
+        agent: &str,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            transport,
+            host: host_header(host),
             root: root.into(),
-            channel: channel.into(),
-            messages: Vec::new(),
-            timetoken: "0".into(),
+            channels: Some(channel)
+                .filter(|channel| !channel.is_empty())
+                .map(String::from)
+                .into_iter()
+                .collect(),
+            channel_groups: Vec::new(),
+            events: VecDeque::new(),
+            timetoken: Timetoken::default(),
+            subscribed: false,
             subscribe_key: subscribe_key.into(),
-            _secret_key: secret_key.into(),
+            publish_key: String::new(),
+            secret_key: secret_key.into(),
+            auth_key: None,
+            cryptor: None,
+            filter: ECHO_FILTER.into(),
+            presence: false,
+            backfill: false,
+            backfill_from: None,
+            uuid: String::new(),
+            heartbeat: None,
+            region: String::new(),
             agent: agent.into(),
-        };
+            limits: Limits::default(),
+        })
+    }
 
-        match pubnub.subscribe() {
-            Ok(()) => Ok(pubnub),
-            Err(_error) => Err(Error::Subscribe),
-        }
+    /// Replaces the [`Limits`] on response sizes.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
     }
 
-    /// Returns next message on the channel.
+    /// Sends the Access Manager [`AuthKey`] with every request, `None`
+    /// sends none.
+    pub fn set_auth_key(&mut self, auth_key: Option<AuthKey>) {
+        self.auth_key = auth_key;
+    }
+
+    /// Decrypts received messages with the [`Cryptor`], `None` keeps
+    /// them as received. String messages it fails to decrypt, such as
+    /// ones encrypted with another cipher key, are received as
+    /// [`Event::Undecryptable`].
+    pub fn set_cryptor(&mut self, cryptor: Option<Cryptor>) {
+        self.cryptor = cryptor;
+    }
+
+    /// Replaces the subscribed channels, each prefixed with the root.
+    /// Takes effect with the next subscribe request.
+    pub fn set_channels(&mut self, channels: &[&str]) {
+        self.channels =
+            channels.iter().map(|&channel| channel.into()).collect();
+    }
+
+    /// Replaces the subscribed channel groups, not prefixed with the root.
+    /// Takes effect with the next subscribe request.
+    pub fn set_channel_groups(&mut self, channel_groups: &[&str]) {
+        self.channel_groups =
+            channel_groups.iter().map(|&group| group.into()).collect();
+    }
+
+    /// Resumes the subscription after `timetoken`, such as the one of the
+    /// last message processed before a restart. Takes effect with the
+    /// next subscribe request.
+    pub fn set_timetoken(&mut self, timetoken: Timetoken) {
+        self.timetoken = timetoken;
+        self.region.clear();
+        self.backfill_from = Some(timetoken);
+    }
+
+    /// Recovers the messages published since the [`set_timetoken`]
+    /// timetoken from Message Persistence when `enabled`, even after the
+    /// subscribe window passed. They're returned oldest first before any
+    /// live message.
+    ///
+    /// History has no channel group or wildcard support, those only
+    /// recover what the subscription from the timetoken still returns.
+    /// Messages published by the bridge are skipped. History can't apply
+    /// a [`set_filter_expression`] filter, with one or when history is
+    /// denied, only the subscription recovers messages and the gap is
+    /// logged.
+    ///
+    /// [`set_timetoken`]: SubscribeClient::set_timetoken
+    /// [`set_filter_expression`]: SubscribeClient::set_filter_expression
+    pub fn set_backfill(&mut self, enabled: bool) {
+        self.backfill = enabled;
+    }
+
+    /// Only receives messages matching the `PubNub` filter `expression`
+    /// on their meta, such as `type == 'telemetry'`. Messages published
+    /// by the bridge stay filtered out.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidFilter`] for an empty expression or one
+    /// with unbalanced quotes or parentheses.
+    pub fn set_filter_expression(
+        &mut self,
+        expression: &str,
+    ) -> Result<(), Error> {
+        validate_filter(expression)?;
+        self.filter = format!("({}) && {ECHO_FILTER}", expression.trim());
+        Ok(())
+    }
+
+    /// Sets the publish key of the keyset, part of the signed string when
+    /// requests are signed with the secret key.
+    pub fn set_publish_key(&mut self, publish_key: &str) {
+        self.publish_key = publish_key.into();
+    }
+
+    /// Identifies the client with `uuid` on every request, so `PubNub`
+    /// counts a single client across reconnects and restarts. An empty
+    /// `uuid` sends none.
+    pub fn set_uuid(&mut self, uuid: &str) {
+        self.uuid = uuid.into();
+    }
+
+    /// Asks `PubNub` to consider the client gone `timeout` seconds after
+    /// its last subscribe or [`heartbeat`] request, `None` keeps the
+    /// keyset default. Takes effect with the next subscribe request.
+    ///
+    /// [`heartbeat`]: SubscribeClient::heartbeat
+    pub fn set_heartbeat(&mut self, timeout: Option<u32>) {
+        self.heartbeat = timeout;
+    }
+
+    /// Also subscribes to the presence channels of every channel and
+    /// channel group when `enabled`, their events are returned by
+    /// [`next_event`]. Takes effect with the next subscribe request.
+    ///
+    /// [`next_event`]: SubscribeClient::next_event
+    pub fn set_presence(&mut self, enabled: bool) {
+        self.presence = enabled;
+    }
+
+    /// Returns next message on the channel, skipping presence events and
+    /// undecryptable messages.
     ///
     /// # Errors
     ///
-    /// This function can return [`Error::SubscribeRead`] on unsuccessful read.
-    pub fn next_message(&mut self) -> Result<Message, Error> {
-        // Return next saved mesasge
-        if let Some(message) = self.messages.pop() {
-            return Ok(message);
+    /// Returns the same errors as [`next_event`].
+    ///
+    /// [`next_event`]: SubscribeClient::next_event
+    pub async fn next_message(&mut self) -> Result<Message, Error> {
+        loop {
+            if let Event::Message(message) = self.next_event().await? {
+                return Ok(message);
+            }
         }
+    }
 
-        // Capture
-        let response: JsonValue = match http_response(&mut self.socket) {
-            Ok(data) => data,
-            Err(_error) => {
-                // Already returning an error, would you like another?
-                let _ = self.subscribe().is_err();
+    /// Returns next message or presence event on the channel, or a
+    /// message the cryptor failed to decrypt as [`Event::Undecryptable`].
+    ///
+    /// # Errors
+    ///
+    /// * [`Error::Socket`] when the subscribe request or response fails
+    /// * [`Error::HTTPStatus`] when `PubNub` rejects the subscribe request
+    /// * [`Error::AccessDenied`] when Access Manager denies the auth key
+    /// * [`Error::AuthKey`] when the auth key file can't be read
+    /// * [`Error::HTTPResponse`] or [`Error::Parse`] on a malformed
+    ///   response
+    pub async fn next_event(&mut self) -> Result<Event, Error> {
+        loop {
+            // Return next saved event, oldest first
+            if let Some(event) = self.events.pop_front() {
+                return Ok(event);
+            }
 
-                // Return first error
-                return Err(Error::SubscribeRead);
+            // Start subscription
+            if !self.subscribed {
+                self.subscribe().await?;
             }
-        };
 
-        // Save Last Received Netwrok Queue ID
-        self.timetoken = response["t"]["t"].to_string();
+            // Capture
+            let response: JsonValue = match http_response(
+                &mut self.transport,
+                &self.limits,
+            )
+            .await
+            {
+                Ok(data) => data,
+                Err(error) => {
+                    // Already returning an error, would you like another?
+                    let _ = self.subscribe().await.is_err();
+
+                    // Return first error
+                    return Err(error);
+                }
+            };
+
+            // Save Last Received Network Queue ID and its region
+            self.timetoken = match response["t"]["t"].to_string().parse() {
+                Ok(timetoken) => timetoken,
+                Err(error) => {
+                    self.subscribed = false;
+                    return Err(Error::Parse(format!("{error}")));
+                }
+            };
+            self.region = if response["t"]["r"].is_null() {
+                String::new()
+            } else {
+                response["t"]["r"].to_string()
+            };
+
+            // Recover messages published while the bridge was down, ahead
+            // of the live ones
+            let backfilled = match self.backfill_from.take() {
+                Some(from) if self.backfill => {
+                    match self.backfill(from).await {
+                        Ok(backfilled) => backfilled,
+                        // Retrying can't recover the history, receive the
+                        // live messages without it
+                        Err(error) if !error.is_retryable() => {
+                            log_gap(from, self.timetoken, &error);
+                            false
+                        }
+                        // Subscribe from the same timetoken on retry
+                        Err(error) => {
+                            self.timetoken = from;
+                            self.region.clear();
+                            self.backfill_from = Some(from);
+                            self.subscribed = false;
+                            return Err(error);
+                        }
+                    }
+                }
+                _ => false,
+            };
+
+            // Capture Messages in publish order
+            for message in response["m"].members() {
+                let channel = message["c"].to_string();
+                let meta = message["u"].to_string();
+
+                // Matched wildcard or channel group, absent for channels
+                let subscription = match message["b"].as_str() {
+                    Some(subscription) => subscription.to_string(),
+                    None => channel.clone(),
+                };
+
+                // Without presence a `-pnpres` channel is a regular one
+                let presence = channel
+                    .strip_suffix(PRESENCE_SUFFIX)
+                    .filter(|_| self.presence);
+                if let Some(channel) = presence {
+                    let subscription = subscription
+                        .strip_suffix(PRESENCE_SUFFIX)
+                        .unwrap_or(&subscription);
+                    self.push_presence(channel, subscription, message);
+                    continue;
+                }
+
+                // History returned the messages of channels, not the ones
+                // of wildcards and channel groups
+                if backfilled && message["b"].is_null() {
+                    continue;
+                }
 
-        // Capture Messages in Vec Buffer
-        for message in response["m"].members() {
-            // Carefully deal with ROOT.CHANNEL
-            let source = message["c"].to_string();
-            let meta = message["u"].to_string();
+                let received = Message {
+                    root: self.root.clone(),
+                    channel: self.unrooted(channel),
+                    subscription: self.unrooted(subscription),
+                    data: String::new(),
+                    metadata: meta,
+                    timetoken: Timetoken::new(number(&message["p"]["t"])),
+                    region: u32::try_from(number(&message["p"]["r"]))
+                        .unwrap_or_default(),
+                    publisher: message["i"].as_str().unwrap_or("").into(),
+                    message_type: number(&message["e"]).into(),
+                    sequence: number(&message["s"]),
+                    flags: number(&message["f"]),
+                };
+                self.events.push_back(message_event(
+                    self.cryptor.as_ref(),
+                    received,
+                    &message["d"],
+                ));
+            }
+
+            // Ask for more messages from network
+            self.subscribe().await?;
+        }
+    }
 
+    /// Queues the stored messages published after `from` and up to the
+    /// current timetoken, paging back through the history of every
+    /// channel. Returns `false` without a request when a filter
+    /// expression is set, history can't apply it.
+    async fn backfill(&mut self, from: Timetoken) -> Result<bool, Error> {
+        if self.filter != ECHO_FILTER {
+            log_gap(from, self.timetoken, "History ignores the filter");
+            return Ok(false);
+        }
+        let end = Timetoken::new(from.as_u64() + 1);
+        let newest = Timetoken::new(self.timetoken.as_u64() + 1);
+        let mut backfilled = Vec::new();
+        for channel in self.channels.clone() {
+            if channel.contains('*') {
+                continue;
+            }
             let channel = if self.root.is_empty() {
-                source
+                channel
             } else {
-                source[self.root.len() + 1..].to_string()
+                format!("{}.{channel}", self.root)
             };
 
-            self.messages.push(Message {
-                root: self.root.to_string(),
-                channel,
-                data: message["d"].to_string(),
-                metadata: meta,
-                id: message["p"]["t"].to_string(),
-            });
+            let mut start = newest;
+            loop {
+                let page = self.history(&channel, start, end).await?;
+                let mut oldest = None;
+                for entry in page.members() {
+                    let timetoken: Timetoken = entry["timetoken"]
+                        .to_string()
+                        .parse()
+                        .map_err(|error| Error::Parse(format!("{error}")))?;
+                    oldest = Some(oldest.map_or(timetoken, |oldest| {
+                        std::cmp::min(oldest, timetoken)
+                    }));
+                    if entry["meta"]["source"] == "KAFKA" {
+                        continue;
+                    }
+                    let received = Message {
+                        root: self.root.clone(),
+                        channel: self.unrooted(channel.clone()),
+                        subscription: self.unrooted(channel.clone()),
+                        data: String::new(),
+                        metadata: entry["meta"].to_string(),
+                        timetoken,
+                        region: 0,
+                        publisher: entry["uuid"]
+                            .as_str()
+                            .unwrap_or("")
+                            .into(),
+                        message_type: number(&entry["message_type"]).into(),
+                        sequence: 0,
+                        flags: 0,
+                    };
+                    backfilled.push((
+                        timetoken,
+                        message_event(
+                            self.cryptor.as_ref(),
+                            received,
+                            &entry["message"],
+                        ),
+                    ));
+                }
+                match oldest {
+                    Some(oldest) if page.len() >= HISTORY_PAGE => {
+                        start = oldest;
+                    }
+                    _ => break,
+                }
+            }
         }
 
-        // Ask for more messages from network
-        match self.subscribe() {
-            Ok(()) => self.next_message(),
-            Err(_) => Err(Error::SubscribeRead),
+        backfilled.sort_by_key(|(timetoken, _)| *timetoken);
+        self.events
+            .extend(backfilled.into_iter().map(|(_, event)| event));
+        Ok(true)
+    }
+
+    /// Queues the presence events of a presence channel `message`, one per
+    /// `uuid` of interval events listing the joined and left ones.
+    fn push_presence(
+        &mut self,
+        channel: &str,
+        subscription: &str,
+        message: &JsonValue,
+    ) {
+        let data = &message["d"];
+        let event = |action: &str, uuid: &str, state: &JsonValue| {
+            Event::Presence(PresenceEvent {
+                root: self.root.clone(),
+                channel: self.unrooted(channel.into()),
+                subscription: self.unrooted(subscription.into()),
+                action: action.into(),
+                uuid: uuid.into(),
+                occupancy: data["occupancy"].as_u64().unwrap_or(0),
+                timestamp: data["timestamp"].as_u64().unwrap_or(0),
+                state: state.dump(),
+                timetoken: Timetoken::new(number(&message["p"]["t"])),
+            })
+        };
+
+        let action = data["action"].as_str().unwrap_or_default();
+        let deltas = ["join", "leave", "timeout"];
+        if action != "interval"
+            || deltas.iter().all(|&delta| data[delta].is_null())
+        {
+            let uuid = data["uuid"].as_str().unwrap_or_default();
+            let event = event(action, uuid, &data["data"]);
+            self.events.push_back(event);
+            return;
+        }
+        let mut events = Vec::new();
+        for &delta in &deltas {
+            for uuid in data[delta].members() {
+                let uuid = uuid.as_str().unwrap_or_default();
+                events.push(event(delta, uuid, &JsonValue::Null));
+            }
+        }
+        self.events.extend(events);
+    }
+
+    /// Fetches the messages of `channel` stored from `end` up to but not
+    /// including `start`, the newest [`HISTORY_PAGE`] of them.
+    async fn history(
+        &mut self,
+        channel: &str,
+        start: Timetoken,
+        end: Timetoken,
+    ) -> Result<JsonValue, Error> {
+        let path = format!(
+            "/v3/history/sub-key/{}/channel/{}",
+            self.subscribe_key,
+            utf8_percent_encode(channel, CHANNEL)
+        );
+        let query = query(
+            &self.secret_key,
+            &self.publish_key,
+            self.auth_key.as_ref(),
+            "GET",
+            &path,
+            [
+                client_parameters(&self.agent, &self.uuid),
+                vec![
+                    ("start", start.to_string()),
+                    ("end", end.to_string()),
+                    ("max", HISTORY_PAGE.to_string()),
+                    ("include_meta", "true".into()),
+                    ("include_uuid", "true".into()),
+                    ("include_message_type", "true".into()),
+                ],
+            ]
+            .concat(),
+            "",
+        )?;
+        let request = format!(
+            "GET {path}?{query} HTTP/1.1\r\nHost: {}\r\n\
+             Accept-Encoding: {}\r\n\r\n",
+            self.host,
+            http::ACCEPT_ENCODING
+        );
+        self.transport.write_all(request.as_bytes()).await?;
+        let mut response =
+            http_response(&mut self.transport, &self.limits).await?;
+        Ok(response["channels"].remove(channel))
+    }
+
+    /// Removes the `ROOT.` prefix of `channel`, kept on channels outside
+    /// the root such as channel group members.
+    fn unrooted(&self, channel: String) -> String {
+        if self.root.is_empty() {
+            return channel;
+        }
+        match channel.strip_prefix(&format!("{}.", self.root)) {
+            Some(unrooted) => unrooted.into(),
+            None => channel,
         }
     }
 
-    fn subscribe(&mut self) -> Result<(), Error> {
-        // Don't subscribe if without a channel
-        if self.channel.is_empty() {
+    /// Comma separated `ROOT.CHANNEL` list, each followed by its presence
+    /// channel when `presence`, a lone comma for none.
+    fn channel_list(&self, presence: bool) -> String {
+        if self.channels.is_empty() {
+            return ",".into();
+        }
+        self.channels
+            .iter()
+            .flat_map(|channel| {
+                let channel = if self.root.is_empty() {
+                    channel.clone()
+                } else {
+                    format!("{}.{channel}", self.root)
+                };
+                let presence = Some(format!("{channel}{PRESENCE_SUFFIX}"))
+                    .filter(|_| presence);
+                std::iter::once(channel).chain(presence)
+            })
+            .map(|channel| utf8_percent_encode(&channel, CHANNEL).to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Comma separated channel group list, followed by their presence
+    /// channel groups when `presence`, `None` without channel groups.
+    fn channel_group_list(&self, presence: bool) -> Option<String> {
+        if self.channel_groups.is_empty() {
+            return None;
+        }
+        let mut channel_groups = self.channel_groups.clone();
+        if presence {
+            channel_groups.extend(
+                self.channel_groups
+                    .iter()
+                    .map(|group| format!("{group}{PRESENCE_SUFFIX}")),
+            );
+        }
+        Some(channel_groups.join(","))
+    }
+
+    /// Tells `PubNub` the client is still subscribed to its channels and
+    /// channel groups, keeping it present between subscribe requests when
+    /// they're further apart than the [`set_heartbeat`] timeout. Cancels
+    /// the pending subscribe request, use a separate client to keep the
+    /// subscription open.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`next_event`].
+    ///
+    /// [`set_heartbeat`]: SubscribeClient::set_heartbeat
+    /// [`next_event`]: SubscribeClient::next_event
+    pub async fn heartbeat(&mut self) -> Result<(), Error> {
+        let mut parameters = Vec::new();
+        if let Some(heartbeat) = self.heartbeat {
+            parameters.push(("heartbeat", heartbeat.to_string()));
+        }
+        self.presence_request("heartbeat", parameters).await
+    }
+
+    /// Tells `PubNub` the client left its channels and channel groups,
+    /// such as on shutdown, instead of timing out. The pending subscribe
+    /// request is cancelled, the next call to [`next_event`] subscribes
+    /// again.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`next_event`].
+    ///
+    /// [`next_event`]: SubscribeClient::next_event
+    pub async fn leave(&mut self) -> Result<(), Error> {
+        self.presence_request("leave", Vec::new()).await
+    }
+
+    /// Sends the presence `action` request for the subscribed channels and
+    /// channel groups.
+    async fn presence_request(
+        &mut self,
+        action: &str,
+        extra: Vec<(&str, String)>,
+    ) -> Result<(), Error> {
+        if self.channels.is_empty() && self.channel_groups.is_empty() {
             return Err(Error::MissingChannel);
         }
-        let channel = if self.root.is_empty() {
-            self.channel.to_string()
-        } else {
-            format!(
-                "{root}.{channel}",
-                channel = self.channel,
-                root = self.root
-            )
-        };
-        let uri = format!(
-            "/v2/subscribe/{subscribe_key}/{channel}/0/{timetoken}?pnsdk={agent}&filter-expr={filter}",
+
+        // The pending subscribe response would be read as ours
+        if self.subscribed {
+            self.transport.disconnect().await;
+            self.subscribed = false;
+        }
+
+        let path = format!(
+            "/v2/presence/sub-key/{}/channel/{}/{action}",
+            self.subscribe_key,
+            self.channel_list(false),
+        );
+        let mut parameters = client_parameters(&self.agent, &self.uuid);
+        parameters.extend(extra);
+        if let Some(channel_groups) = self.channel_group_list(false) {
+            parameters.push(("channel-group", channel_groups));
+        }
+        let query = query(
+            &self.secret_key,
+            &self.publish_key,
+            self.auth_key.as_ref(),
+            "GET",
+            &path,
+            parameters,
+            "",
+        )?;
+        let request = format!(
+            "GET {path}?{query} HTTP/1.1\r\nHost: {}\r\n\
+             Accept-Encoding: {}\r\n\r\n",
+            self.host,
+            http::ACCEPT_ENCODING
+        );
+        self.transport.write_all(request.as_bytes()).await?;
+        http_response(&mut self.transport, &self.limits).await?;
+        Ok(())
+    }
+
+    async fn subscribe(&mut self) -> Result<(), Error> {
+        if self.channels.is_empty() && self.channel_groups.is_empty() {
+            return Err(Error::MissingChannel);
+        }
+
+        let channel = self.channel_list(self.presence);
+        let mut parameters = client_parameters(&self.agent, &self.uuid);
+        parameters.push(("filter-expr", self.filter.clone()));
+        if let Some(channel_groups) = self.channel_group_list(self.presence) {
+            parameters.push(("channel-group", channel_groups));
+        }
+        if let Some(heartbeat) = self.heartbeat {
+            parameters.push(("heartbeat", heartbeat.to_string()));
+        }
+
+        // Stay on the data center of the timetoken
+        let timetoken = self.timetoken;
+        if timetoken != Timetoken::default() && !self.region.is_empty() {
+            parameters.push(("tr", self.region.clone()));
+        }
+        let path = format!(
+            "/v2/subscribe/{subscribe_key}/{channel}/0/{timetoken}",
             subscribe_key = self.subscribe_key,
             channel = channel,
-            timetoken = self.timetoken,
-            agent = self.agent,
-            filter = "source%20!%3D%20'KAFKA'".to_string(),
+            timetoken = timetoken,
         );
-        let request =
-            format!("GET {} HTTP/1.1\r\nHost: pubnub\r\n\r\n", uri,);
-        match self.socket.write(request) {
-            Ok(_size) => Ok(()),
-            Err(_error) => Err(Error::SubscribeWrite),
+        let query = query(
+            &self.secret_key,
+            &self.publish_key,
+            self.auth_key.as_ref(),
+            "GET",
+            &path,
+            parameters,
+            "",
+        )?;
+        let uri = format!("{path}?{query}");
+        let request = format!(
+            "GET {uri} HTTP/1.1\r\nHost: {}\r\nAccept-Encoding: {}\r\n\r\n",
+            self.host,
+            http::ACCEPT_ENCODING
+        );
+        match self.transport.write_all(request.as_bytes()).await {
+            Ok(()) => {
+                self.subscribed = true;
+                Ok(())
+            }
+            Err(error) => {
+                self.subscribed = false;
+                Err(error.into())
+            }
         }
     }
 }
@@ -236,29 +1475,35 @@ impl SubscribeClient {
 /// ```no_run
 /// use kafka_bridge::pubnub::PublishClient;
 ///
-/// let host = "psdsn.pubnub.com:80";
-/// let root = "";
-/// let channel = "demo";
-/// let publish_key = "demo";
-/// let subscribe_key = "demo";
-/// let _secret_key = "secret";
-/// let agent = "kafka-bridge";
-/// let mut pubnub = PublishClient::new(
-///     host,
-///     root,
-///     publish_key,
-///     subscribe_key,
-///     _secret_key,
-///     agent,
-///  ).expect("KAFKA Subscribe Client");
+/// #[tokio::main]
+/// async fn main() {
+///     let host = "https://psdsn.pubnub.com";
+///     let root = "";
+///     let channel = "demo";
+///     let publish_key = "demo";
+///     let subscribe_key = "demo";
+///     let secret_key = "secret";
+///     let agent = "kafka-bridge";
+///     let mut pubnub = PublishClient::new(
+///         host,
+///         root,
+///         publish_key,
+///         subscribe_key,
+///         secret_key,
+///         agent,
+///     ).expect("KAFKA Subscribe Client");
 ///
-/// let result = pubnub.publish(channel, "data");
-/// assert!(result.is_ok());
+///     let result = pubnub.publish(channel, "\"data\"").await;
+///     assert!(result.is_ok());
+/// }
 /// ```
 // =-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=
 impl PublishClient {
     /// Creates a new [`PublishClient`].
     ///
+    /// The `host` origin selects the transport, `https://` origins and
+    /// port `443` are encrypted with TLS.
+    ///
     /// # Errors
     ///
     /// This function returns no errors.
@@ -270,57 +1515,1220 @@ impl PublishClient {
         secret_key: &str,
         agent: &str,
     ) -> Result<Self, Error> {
-        let socket = Socket::new(host, agent, 5);
+        Self::new_with_tls(
+            host,
+            root,
+            publish_key,
+            subscribe_key,
+            secret_key,
+            agent,
+            &TlsConfig::default(),
+        )
+    }
 
-        Ok(Self {
+    /// Creates a new [`PublishClient`] using `tls` to verify `https://`
+    /// origins.
+    ///
+    /// # Errors
+    ///
+    /// This function returns no errors.
+    pub fn new_with_tls(
+        host: &str,
+        root: &str,
+        publish_key: &str,
+        subscribe_key: &str,
+        secret_key: &str,
+        agent: &str,
+        tls: &TlsConfig,
+    ) -> Result<Self, Error> {
+        let socket = connect(host, agent, 5, tls);
+        PublishClient::with_transport(
             socket,
+            host,
+            root,
+            publish_key,
+            subscribe_key,
+            secret_key,
+            agent,
+        )
+    }
+
+    /// Replaces the [`ReconnectPolicy`] used when the connection to
+    /// `PubNub` is lost.
+    pub fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+        self.transport.set_reconnect_policy(policy);
+    }
+
+    /// Replaces the [`Proxy`] taken from the environment, `None`
+    /// connects directly.
+    pub fn set_proxy(&mut self, proxy: Option<Proxy>) {
+        self.transport.set_proxy(proxy);
+    }
+}
+
+impl<T: Transport> PublishClient<T> {
+    /// Creates a new [`PublishClient`] sending its requests through
+    /// `transport`, `host` only fills in the `Host` header.
+    ///
+    /// # Errors
+    ///
+    /// This function returns no errors.
+    pub fn with_transport(
+        transport: T,
+        host: &str,
+        root: &str,
+        publish_key: &str,
+        subscribe_key: &str,
+        secret_key: &str,
+        agent: &str,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            transport,
+            host: host_header(host),
             root: root.into(),
             publish_key: publish_key.into(),
             subscribe_key: subscribe_key.into(),
-            _secret_key: secret_key.into(),
+            secret_key: secret_key.into(),
+            auth_key: None,
+            cryptor: None,
+            meta: ECHO_META.into(),
+            uuid: String::new(),
             agent: agent.into(),
+            limits: Limits::default(),
         })
     }
-    /// Publishes `message` to `channel`.
+
+    /// Replaces the [`Limits`] on response sizes.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Sends the Access Manager [`AuthKey`] with every request, `None`
+    /// sends none.
+    pub fn set_auth_key(&mut self, auth_key: Option<AuthKey>) {
+        self.auth_key = auth_key;
+    }
+
+    /// Identifies the client with `uuid` on every request, an empty `uuid`
+    /// sends none.
+    pub fn set_uuid(&mut self, uuid: &str) {
+        self.uuid = uuid.into();
+    }
+
+    /// Encrypts published messages with the [`Cryptor`], `None` publishes
+    /// them in plaintext.
+    pub fn set_cryptor(&mut self, cryptor: Option<Cryptor>) {
+        self.cryptor = cryptor;
+    }
+
+    /// Publishes messages with the JSON object `meta`, its `source` field
+    /// replaced with `KAFKA` so subscribers of the bridge don't receive
+    /// them back.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidMeta`] when `meta` is not a JSON object.
+    pub fn set_meta(&mut self, meta: &str) -> Result<(), Error> {
+        let mut meta = json::parse(meta)
+            .map_err(|error| Error::InvalidMeta(format!("{error}")))?;
+        if !meta.is_object() {
+            return Err(Error::InvalidMeta("Not a JSON object".into()));
+        }
+        meta["source"] = "KAFKA".into();
+        self.meta = meta.dump();
+        Ok(())
+    }
+
+    /// Publishes `message` to `channel`, sent as the JSON body of a `POST`
+    /// so large messages aren't bound by URL length limits.
     ///
     /// # Errors
     ///
-    /// * [`Error::PublishWrite`] on unsuccessful socket write
-    /// * [`Error::PublishResponse`] on unsuccessful HTTP response
-    pub fn publish(
+    /// * [`Error::Socket`] when the publish request or response fails
+    /// * [`Error::HTTPStatus`] when `PubNub` rejects the message
+    /// * [`Error::AccessDenied`] when Access Manager denies the auth key
+    /// * [`Error::AuthKey`] when the auth key file can't be read
+    /// * [`Error::Crypto`] when the message can't be encrypted
+    /// * [`Error::HTTPResponse`] or [`Error::Parse`] on a malformed
+    ///   response
+    pub async fn publish(
         &mut self,
         channel: &str,
         message: &str,
-    ) -> Result<String, Error> {
-        let encoded_message =
-            utf8_percent_encode(message, NON_ALPHANUMERIC).to_string();
+    ) -> Result<Timetoken, Error> {
+        self.publish_with_options(
+            channel,
+            message,
+            &PublishOptions::default(),
+        )
+        .await
+    }
+
+    /// Publishes `message` to `channel` like [`publish`] with the
+    /// [`PublishOptions`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`publish`], and
+    /// [`Error::InvalidPublishOptions`] for an invalid custom message
+    /// type.
+    ///
+    /// [`publish`]: PublishClient::publish
+    pub async fn publish_with_options(
+        &mut self,
+        channel: &str,
+        message: &str,
+        options: &PublishOptions,
+    ) -> Result<Timetoken, Error> {
+        options.validate()?;
         let channel = if self.root.is_empty() {
             channel.to_string()
         } else {
             format!("{root}.{channel}", channel = channel, root = self.root)
         };
-        let uri = format!(
-            "/publish/{}/{}/0/{}/0/{}?pnsdk={pnsdk}&meta={meta}",
+        // Encrypted messages are sent as a JSON string
+        let body = match &self.cryptor {
+            Some(cryptor) => json::stringify(
+                cryptor.encrypt(message.as_bytes()).map_err(Error::Crypto)?,
+            ),
+            None => message.into(),
+        };
+        let path = format!(
+            "/publish/{}/{}/0/{}/0",
             self.publish_key,
             self.subscribe_key,
-            channel,
-            encoded_message,
-            pnsdk = self.agent,
-            meta = "{\"source\":\"KAFKA\"}"
+            utf8_percent_encode(&channel, NON_ALPHANUMERIC),
         );
+        let query = query(
+            &self.secret_key,
+            &self.publish_key,
+            self.auth_key.as_ref(),
+            "POST",
+            &path,
+            [
+                client_parameters(&self.agent, &self.uuid),
+                vec![("meta", self.meta.clone())],
+                options.parameters(),
+            ]
+            .concat(),
+            &body,
+        )?;
+        let uri = format!("{path}?{query}");
 
-        let request =
-            format!("GET {} HTTP/1.1\r\nHost: pubnub\r\n\r\n", uri,);
-        let _size = match self.socket.write(request) {
-            Ok(size) => size,
-            Err(_error) => return Err(Error::PublishWrite),
-        };
+        let request = format!(
+            "POST {uri} HTTP/1.1\r\nHost: {}\r\nAccept-Encoding: {}\r\n\
+             Content-Type: application/json\r\nContent-Length: {}\r\n\r\n\
+             {body}",
+            self.host,
+            http::ACCEPT_ENCODING,
+            body.len()
+        );
+        self.transport.write_all(request.as_bytes()).await?;
 
         // Capture and return TimeToken
-        let response: JsonValue = match http_response(&mut self.socket) {
-            Ok(data) => data,
-            Err(_error) => return Err(Error::PublishResponse),
+        let response: JsonValue =
+            http_response(&mut self.transport, &self.limits).await?;
+        response[2]
+            .to_string()
+            .parse()
+            .map_err(|error| Error::Parse(format!("{error}")))
+    }
+}
+
+#[cfg(test)]
+mod pubnub_tests {
+    use super::{
+        host_header, http_response, origin, signed_query, AuthKey, Error,
+        Event, Limits, MessageType, PublishClient, PublishOptions,
+        SubscribeClient,
+    };
+    use crate::crypto::{self, Cryptor, Mode};
+    use crate::socket::{self, Socket};
+    use crate::timetoken::Timetoken;
+    use crate::transport::MockTransport;
+    use openssl::error::ErrorStack;
+    use std::io;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    fn http(status: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {status}\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    #[test]
+    fn signed_query_ok() {
+        let query = signed_query(
+            "sec-key",
+            "pub-key",
+            "GET",
+            "/v2/subscribe/sub-key/demo/0/0",
+            vec![
+                ("pnsdk", "agent".into()),
+                ("filter-expr", "source != 'KAFKA'".into()),
+            ],
+            "",
+            1_700_000_000,
+        );
+        assert_eq!(
+            query.expect("query"),
+            "filter-expr=source%20%21%3D%20%27KAFKA%27&pnsdk=agent\
+             &timestamp=1700000000\
+             &signature=v2.eDo8IX0koJcznAKAwe7s2CbpnuBt1fT_Kq0L4vDQunw"
+        );
+
+        let query = signed_query(
+            "sec-key",
+            "pub-key",
+            "POST",
+            "/publish/pub-key/sub-key/0/demo/0",
+            vec![("pnsdk", "agent".into())],
+            r#"{"a":1}"#,
+            1_700_000_000,
+        );
+        assert!(query.expect("query").ends_with(
+            "&signature=v2.PGvqxm2jpoobCVgFbpfTLmC0M6x9KFuvGMydlNvRpOI"
+        ));
+    }
+
+    #[tokio::test]
+    async fn subscribe_signed_ok() {
+        let mut transport = MockTransport::new();
+        transport
+            .push_response(http("200 OK", r#"{"t":{"t":"100"},"m":[]}"#));
+        let requests = transport.requests();
+
+        let mut pubnub = SubscribeClient::with_transport(
+            transport,
+            "ps.pndsn.com",
+            "",
+            "demo",
+            "sub-key",
+            "sec-key",
+            "agent",
+        )
+        .expect("client");
+        pubnub.set_publish_key("pub-key");
+        assert!(pubnub.next_message().await.is_err());
+
+        let requests = requests.lock().expect("requests");
+        assert!(requests[0].contains("&pnsdk=agent&timestamp="));
+        assert!(requests[0].contains("&signature=v2."));
+        assert!(!requests[0].contains("sec-key"));
+    }
+
+    #[tokio::test]
+    async fn uuid_heartbeat_leave_ok() {
+        let mut transport = MockTransport::new();
+        transport.push_response(http(
+            "200 OK",
+            r#"{"t":{"t":"100"},"m":[{"c":"demo","d":1,"p":{"t":"1"}}]}"#,
+        ));
+        transport.push_response(http("200 OK", r#"{"status":200}"#));
+        transport.push_response(http("200 OK", r#"{"status":200}"#));
+        let requests = transport.requests();
+
+        let mut pubnub = SubscribeClient::with_transport(
+            transport,
+            "ps.pndsn.com",
+            "",
+            "demo",
+            "sub-key",
+            "",
+            "agent",
+        )
+        .expect("client");
+        pubnub.set_channel_groups(&["group"]);
+        pubnub.set_presence(true);
+        pubnub.set_uuid("bridge-1");
+        pubnub.set_heartbeat(Some(120));
+        pubnub.next_message().await.expect("message");
+        pubnub.leave().await.expect("leave");
+        pubnub.heartbeat().await.expect("heartbeat");
+
+        let sent = requests.lock().expect("requests").clone();
+        assert!(sent[0].contains("&heartbeat=120&pnsdk=agent&uuid=bridge-1 "));
+        assert!(sent[1].contains("&uuid=bridge-1 "));
+        assert!(sent[2].starts_with(
+            "GET /v2/presence/sub-key/sub-key/channel/demo/leave\
+             ?channel-group=group&pnsdk=agent&uuid=bridge-1 "
+        ));
+        assert!(sent[3].starts_with(
+            "GET /v2/presence/sub-key/sub-key/channel/demo/heartbeat\
+             ?channel-group=group&heartbeat=120&pnsdk=agent&uuid=bridge-1 "
+        ));
+
+        let mut transport = MockTransport::new();
+        transport.push_response(http("200 OK", r#"[1,"Sent","2"]"#));
+        let requests = transport.requests();
+        let mut pubnub = PublishClient::with_transport(
+            transport,
+            "ps.pndsn.com",
+            "",
+            "pub-key",
+            "sub-key",
+            "",
+            "agent",
+        )
+        .expect("client");
+        pubnub.set_uuid("bridge-1");
+        pubnub.publish("demo", "1").await.expect("publish");
+        assert!(requests.lock().expect("requests")[0]
+            .contains("&pnsdk=agent&uuid=bridge-1 "));
+    }
+
+    #[test]
+    fn retryable_ok() {
+        let retryable = [
+            Error::Socket(socket::Error::Eof),
+            Error::HTTPStatus {
+                status: 503,
+                body: String::new(),
+            },
+            Error::HTTPStatus {
+                status: 429,
+                body: String::new(),
+            },
+            Error::HTTPResponse("Invalid header".into()),
+            Error::Parse("Unexpected end of JSON".into()),
+        ];
+        for error in &retryable {
+            assert!(error.is_retryable(), "{}", error);
+        }
+
+        let permanent = [
+            Error::MissingChannel,
+            Error::Socket(socket::Error::TooLarge(1)),
+            Error::HTTPStatus {
+                status: 400,
+                body: String::new(),
+            },
+            Error::AccessDenied(String::new()),
+            Error::AuthKey(io::ErrorKind::NotFound.into()),
+            Error::ResponseTooLarge("Body over 1 bytes".into()),
+            Error::Signature(ErrorStack::get()),
+            Error::Crypto(crypto::Error::Malformed("Invalid base64".into())),
+            Error::InvalidFilter(String::new()),
+            Error::InvalidMeta(String::new()),
+            Error::InvalidPublishOptions(String::new()),
+        ];
+        for error in &permanent {
+            assert!(!error.is_retryable(), "{}", error);
+        }
+    }
+
+    #[test]
+    fn origin_ok() {
+        assert_eq!(
+            origin("https://ps.pndsn.com"),
+            ("ps.pndsn.com:443".into(), true)
+        );
+        assert_eq!(
+            origin("http://ps.pndsn.com/"),
+            ("ps.pndsn.com:80".into(), false)
+        );
+        assert_eq!(
+            origin("ps.pndsn.com:443"),
+            ("ps.pndsn.com:443".into(), true)
+        );
+        assert_eq!(
+            origin("ps.pndsn.com:80"),
+            ("ps.pndsn.com:80".into(), false)
+        );
+        assert_eq!(
+            host_header("https://ps.pndsn.com:8443"),
+            "ps.pndsn.com:8443"
+        );
+        assert_eq!(host_header("ps.pndsn.com:443"), "ps.pndsn.com");
+    }
+
+    #[tokio::test]
+    async fn http_status_error() {
+        let mut listener =
+            TcpListener::bind("127.0.0.1:0").await.expect("listener");
+        let host = listener.local_addr().expect("address").to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.expect("accept");
+            let response = "HTTP/1.1 403 Forbidden\r\nContent-Length: 9\r\n\r\n\
+                            Forbidden\
+                            HTTP/1.1 503 Unavailable\r\nContent-Length: 0\r\n\r\n\
+                            HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\n[1,2";
+            stream
+                .write_all(response.as_bytes())
+                .await
+                .expect("response");
+        });
+
+        let mut socket = Socket::new(&host, "HTTP Agent", 5);
+        let error = http_response(&mut socket, &Limits::default())
+            .await
+            .expect_err("403");
+        assert!(matches!(error, Error::AccessDenied(_)));
+        assert!(!error.is_retryable());
+
+        let error = http_response(&mut socket, &Limits::default())
+            .await
+            .expect_err("503");
+        assert!(matches!(error, Error::HTTPStatus { status: 503, .. }));
+        assert!(error.is_retryable());
+
+        assert!(matches!(
+            http_response(&mut socket, &Limits::default()).await,
+            Err(Error::Parse(_))
+        ));
+        assert!(matches!(
+            http_response(&mut socket, &Limits::default()).await,
+            Err(Error::Socket(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn subscribe_ok() {
+        let mut transport = MockTransport::new();
+        transport.push_response(http(
+            "200 OK",
+            r#"{"t":{"t":"100","r":12},"m":[]}"#,
+        ));
+        transport.push_response(http(
+            "200 OK",
+            r#"{"t":{"t":"200","r":12},"m":[{"c":"root.demo","d":{"a":1},"u":{"source":"APP"},"i":"client-1","e":1,"s":7,"f":0,"p":{"t":"150","r":12}}]}"#,
+        ));
+        let requests = transport.requests();
+
+        let mut pubnub = SubscribeClient::with_transport(
+            transport,
+            "https://ps.pndsn.com",
+            "root",
+            "demo",
+            "sub-key",
+            "secret",
+            "agent",
+        )
+        .expect("client");
+        let message = pubnub.next_message().await.expect("message");
+        assert_eq!(message.root, "root");
+        assert_eq!(message.channel, "demo");
+        assert_eq!(message.data, r#"{"a":1}"#);
+        assert_eq!(message.metadata, r#"{"source":"APP"}"#);
+        assert_eq!(message.timetoken, Timetoken::new(150));
+        assert_eq!(message.region, 12);
+        assert_eq!(message.publisher, "client-1");
+        assert_eq!(message.message_type, MessageType::Signal);
+        assert_eq!(message.sequence, 7);
+        assert_eq!(message.flags, 0);
+
+        // Timetoken and region advance with every response
+        let requests = requests.lock().expect("requests");
+        assert_eq!(requests.len(), 3);
+        assert!(requests[0]
+            .starts_with("GET /v2/subscribe/sub-key/root.demo/0/0?"));
+        assert!(requests[0].ends_with(
+            "\r\nHost: ps.pndsn.com\r\nAccept-Encoding: gzip, deflate\r\n\r\n"
+        ));
+        assert!(!requests[0].contains("&tr="));
+        assert!(requests[1].contains("/root.demo/0/100?"));
+        assert!(requests[1].contains("&tr=12&"));
+        assert!(requests[2].contains("/root.demo/0/200?"));
+    }
+
+    #[tokio::test]
+    async fn subscribe_channels_ok() {
+        let mut transport = MockTransport::new();
+        transport.push_response(http(
+            "200 OK",
+            r#"{"t":{"t":"100"},"m":[
+                {"c":"root.a","d":1,"p":{"t":"1"}},
+                {"c":"root.b.c","b":"root.b.*","d":2,"p":{"t":"2"}},
+                {"c":"other","b":"group","d":3,"p":{"t":"3"}}]}"#,
+        ));
+        let requests = transport.requests();
+
+        let mut pubnub = SubscribeClient::with_transport(
+            transport,
+            "ps.pndsn.com",
+            "root",
+            "a",
+            "sub-key",
+            "",
+            "agent",
+        )
+        .expect("client");
+        pubnub.set_channels(&["a", "b.*", "c d"]);
+        pubnub.set_channel_groups(&["group", "cg,2"]);
+
+        let message = pubnub.next_message().await.expect("message");
+        assert_eq!(message.channel, "a");
+        assert_eq!(message.subscription, "a");
+        let message = pubnub.next_message().await.expect("message");
+        assert_eq!(message.channel, "b.c");
+        assert_eq!(message.subscription, "b.*");
+        let message = pubnub.next_message().await.expect("message");
+        assert_eq!(message.channel, "other");
+        assert_eq!(message.subscription, "group");
+
+        let requests = requests.lock().expect("requests");
+        assert!(requests[0].starts_with(
+            "GET /v2/subscribe/sub-key/root.a,root.b.*,root.c%20d/0/0\
+             ?channel-group=group%2Ccg%2C2&"
+        ));
+    }
+
+    #[tokio::test]
+    async fn presence_ok() {
+        let mut transport = MockTransport::new();
+        transport.push_response(http(
+            "200 OK",
+            r#"{"t":{"t":"100"},"m":[
+                {"c":"root.a-pnpres","d":{"action":"join","uuid":"device-1",
+                    "occupancy":1,"timestamp":1700000000},"p":{"t":"1"}},
+                {"c":"root.a","d":"hello","p":{"t":"2"}},
+                {"c":"root.a-pnpres","d":{"action":"state-change",
+                    "uuid":"device-1","occupancy":1,"timestamp":1700000001,
+                    "data":{"battery":80}},"p":{"t":"3"}},
+                {"c":"root.b.c-pnpres","b":"root.b.*-pnpres",
+                    "d":{"action":"interval","occupancy":1,
+                    "timestamp":1700000002,"join":["device-2"],
+                    "timeout":["device-3"]},"p":{"t":"4"}}]}"#,
+        ));
+        let requests = transport.requests();
+
+        let mut pubnub = SubscribeClient::with_transport(
+            transport,
+            "ps.pndsn.com",
+            "root",
+            "a",
+            "sub-key",
+            "",
+            "agent",
+        )
+        .expect("client");
+        pubnub.set_channels(&["a", "b.*"]);
+        pubnub.set_channel_groups(&["group"]);
+        pubnub.set_presence(true);
+
+        let mut events = Vec::new();
+        for _ in 0..5 {
+            events.push(pubnub.next_event().await.expect("event"));
+        }
+        let presence: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Presence(presence) => Some(presence),
+                _ => None,
+            })
+            .collect();
+        assert!(matches!(&events[1], Event::Message(message)
+            if message.data == "hello"));
+        assert_eq!(presence.len(), 4);
+        assert_eq!(presence[0].channel, "a");
+        assert_eq!(presence[0].action, "join");
+        assert_eq!(presence[0].uuid, "device-1");
+        assert_eq!(presence[0].occupancy, 1);
+        assert_eq!(presence[0].timestamp, 1_700_000_000);
+        assert_eq!(presence[0].timetoken, Timetoken::new(1));
+        assert_eq!(
+            presence[1].to_json(),
+            r#"{"channel":"a","action":"state-change","uuid":"device-1","occupancy":1,"timestamp":1700000001,"state":{"battery":80}}"#
+        );
+        assert_eq!(presence[2].channel, "b.c");
+        assert_eq!(presence[2].subscription, "b.*");
+        assert_eq!(presence[2].action, "join");
+        assert_eq!(presence[2].uuid, "device-2");
+        assert_eq!(presence[3].action, "timeout");
+        assert_eq!(presence[3].uuid, "device-3");
+        assert_eq!(presence[3].state, "null");
+
+        let requests = requests.lock().expect("requests");
+        assert!(requests[0].starts_with(
+            "GET /v2/subscribe/sub-key/\
+             root.a,root.a-pnpres,root.b.*,root.b.*-pnpres/0/0\
+             ?channel-group=group%2Cgroup-pnpres&"
+        ));
+    }
+
+    #[tokio::test]
+    async fn presence_disabled_ok() {
+        let mut transport = MockTransport::new();
+        transport.push_response(http(
+            "200 OK",
+            r#"{"t":{"t":"100"},"m":[
+                {"c":"a-pnpres","d":{"action":"join"},"p":{"t":"1"}}]}"#,
+        ));
+        let mut pubnub = SubscribeClient::with_transport(
+            transport,
+            "ps.pndsn.com",
+            "",
+            "a-pnpres",
+            "sub-key",
+            "",
+            "agent",
+        )
+        .expect("client");
+
+        // Channels named like presence channels are regular ones
+        let message = pubnub.next_message().await.expect("message");
+        assert_eq!(message.channel, "a-pnpres");
+        assert_eq!(message.data, r#"{"action":"join"}"#);
+    }
+
+    #[tokio::test]
+    async fn filter_and_meta_ok() {
+        let mut transport = MockTransport::new();
+        transport.push_write_error(socket::Error::Eof);
+        let requests = transport.requests();
+        let mut pubnub = SubscribeClient::with_transport(
+            transport,
+            "ps.pndsn.com",
+            "",
+            "demo",
+            "sub-key",
+            "",
+            "agent",
+        )
+        .expect("client");
+        for expression in &["", " ", "a == 'b", "(a == 'b'", "a == 'b')"] {
+            let error = pubnub.set_filter_expression(expression);
+            assert!(
+                matches!(error, Err(Error::InvalidFilter(_))),
+                "{}",
+                expression
+            );
+        }
+        pubnub
+            .set_filter_expression("type == 'telemetry' || x == ')'")
+            .expect("filter");
+        assert!(pubnub.next_message().await.is_err());
+        assert!(requests.lock().expect("requests")[0].contains(
+            "filter-expr=%28type%20%3D%3D%20%27telemetry%27%20%7C%7C\
+             %20x%20%3D%3D%20%27%29%27%29%20%26%26\
+             %20source%20%21%3D%20%27KAFKA%27&"
+        ));
+
+        let mut transport = MockTransport::new();
+        transport.push_response(http("200 OK", r#"[1,"Sent","160"]"#));
+        let requests = transport.requests();
+        let mut pubnub = PublishClient::with_transport(
+            transport,
+            "ps.pndsn.com",
+            "",
+            "pub-key",
+            "sub-key",
+            "",
+            "agent",
+        )
+        .expect("client");
+        for meta in &["[1]", "{", "\"KAFKA\""] {
+            let error = pubnub.set_meta(meta);
+            assert!(matches!(error, Err(Error::InvalidMeta(_))), "{}", meta);
+        }
+        pubnub
+            .set_meta(r#"{"type":"telemetry","source":"APP"}"#)
+            .expect("meta");
+        pubnub.publish("demo", "1").await.expect("published");
+        assert!(requests.lock().expect("requests")[0].contains(
+            "?meta=%7B%22type%22%3A%22telemetry%22%2C\
+             %22source%22%3A%22KAFKA%22%7D&"
+        ));
+    }
+
+    #[tokio::test]
+    async fn set_timetoken_ok() {
+        let mut transport = MockTransport::new();
+        transport.push_write_error(socket::Error::Eof);
+        let requests = transport.requests();
+        let mut pubnub = SubscribeClient::with_transport(
+            transport,
+            "ps.pndsn.com",
+            "",
+            "demo",
+            "sub-key",
+            "",
+            "agent",
+        )
+        .expect("client");
+        pubnub.set_timetoken(Timetoken::new(15_000_000_000_000_000));
+        assert!(pubnub.next_message().await.is_err());
+        assert!(requests.lock().expect("requests")[0].starts_with(
+            "GET /v2/subscribe/sub-key/demo/0/15000000000000000?"
+        ));
+    }
+
+    #[tokio::test]
+    async fn message_order_ok() {
+        let mut transport = MockTransport::new();
+        transport.push_response(http(
+            "200 OK",
+            r#"{"t":{"t":"300"},"m":[
+                {"c":"demo","d":1,"p":{"t":"100"}},
+                {"c":"demo","d":2,"p":{"t":"200"}},
+                {"c":"demo","d":3,"p":{"t":"300"}}]}"#,
+        ));
+        transport
+            .push_response(http("200 OK", r#"{"t":{"t":"300"},"m":[]}"#));
+        transport
+            .push_response(http("200 OK", r#"{"t":{"t":"400"},"m":[]}"#));
+        transport.push_response(http(
+            "200 OK",
+            r#"{"t":{"t":"600"},"m":[
+                {"c":"demo","d":4,"p":{"t":"500"}},
+                {"c":"demo","d":5,"p":{"t":"600"}}]}"#,
+        ));
+        let requests = transport.requests();
+
+        let mut pubnub = SubscribeClient::with_transport(
+            transport,
+            "ps.pndsn.com",
+            "",
+            "demo",
+            "sub-key",
+            "",
+            "agent",
+        )
+        .expect("client");
+        for expected in &["1", "2", "3", "4", "5"] {
+            let message = pubnub.next_message().await.expect("message");
+            assert_eq!(message.data, *expected);
+        }
+
+        // Empty batches are polled again without returning
+        assert_eq!(requests.lock().expect("requests").len(), 5);
+    }
+
+    #[tokio::test]
+    async fn backfill_ok() {
+        // Channel messages of the first response are in history too
+        let mut transport = MockTransport::new();
+        transport.push_response(http(
+            "200 OK",
+            r#"{"t":{"t":"500"},"m":[
+                {"c":"root.demo","d":{"n":2},"p":{"t":"300"}},
+                {"c":"root.other","b":"group","d":{"n":4},"p":{"t":"500"}}
+            ]}"#,
+        ));
+        transport.push_response(http(
+            "200 OK",
+            r#"{"status":200,"channels":{"root.demo":[
+                {"message":{"n":2},"timetoken":"300","meta":{}},
+                {"message":{"n":1},"timetoken":"200"},
+                {"message":"echo","timetoken":"400","meta":{"source":"KAFKA"}}
+            ]}}"#,
+        ));
+        transport.push_response(http(
+            "200 OK",
+            r#"{"t":{"t":"600"},"m":[{"c":"root.demo","d":{"n":3},"p":{"t":"600"}}]}"#,
+        ));
+        let requests = transport.requests();
+
+        let mut pubnub = SubscribeClient::with_transport(
+            transport,
+            "ps.pndsn.com",
+            "root",
+            "demo",
+            "sub-key",
+            "",
+            "agent",
+        )
+        .expect("client");
+        pubnub.set_channel_groups(&["group"]);
+        pubnub.set_backfill(true);
+        pubnub.set_timetoken(Timetoken::new(100));
+
+        for (data, timetoken, channel) in &[
+            (r#"{"n":1}"#, 200, "demo"),
+            (r#"{"n":2}"#, 300, "demo"),
+            (r#"{"n":4}"#, 500, "other"),
+        ] {
+            let message = pubnub.next_message().await.expect("backfilled");
+            assert_eq!(
+                (message.data.as_str(), message.timetoken),
+                (*data, Timetoken::new(*timetoken))
+            );
+            assert_eq!(message.channel, *channel);
+        }
+        let message = pubnub.next_message().await.expect("live");
+        assert_eq!(message.timetoken, Timetoken::new(600));
+
+        // Resumes from the timetoken, history includes the first response
+        let requests = requests.lock().expect("requests");
+        assert!(requests[0]
+            .starts_with("GET /v2/subscribe/sub-key/root.demo/0/100?"));
+        assert!(requests[1].starts_with(
+            "GET /v3/history/sub-key/sub-key/channel/root.demo\
+             ?end=101&include_message_type=true&include_meta=true\
+             &include_uuid=true&max=100&pnsdk=agent&start=501 "
+        ));
+        assert!(requests[2]
+            .starts_with("GET /v2/subscribe/sub-key/root.demo/0/500?"));
+    }
+
+    #[tokio::test]
+    async fn backfill_skipped_ok() {
+        // History denied, the subscription still delivers live messages
+        let mut transport = MockTransport::new();
+        transport.push_response(http(
+            "200 OK",
+            r#"{"t":{"t":"500"},"m":[
+                {"c":"demo","d":{"n":4},"p":{"t":"450"}}]}"#,
+        ));
+        transport.push_response(http("403 Forbidden", r#"{"status":403}"#));
+        let requests = transport.requests();
+        let mut pubnub = SubscribeClient::with_transport(
+            transport,
+            "ps.pndsn.com",
+            "",
+            "demo",
+            "sub-key",
+            "",
+            "agent",
+        )
+        .expect("client");
+        pubnub.set_backfill(true);
+        pubnub.set_timetoken(Timetoken::new(100));
+
+        let message = pubnub.next_message().await.expect("live");
+        assert_eq!(message.timetoken, Timetoken::new(450));
+        let sent = requests.lock().expect("requests").clone();
+        assert!(sent[1].starts_with("GET /v3/history/"));
+        assert!(sent[2].starts_with("GET /v2/subscribe/sub-key/demo/0/500?"));
+
+        // History can't apply the filter expression, it isn't requested
+        let mut transport = MockTransport::new();
+        transport.push_response(http(
+            "200 OK",
+            r#"{"t":{"t":"500"},"m":[
+                {"c":"demo","d":{"n":4},"u":{"type":"a"},"p":{"t":"450"}}]}"#,
+        ));
+        let requests = transport.requests();
+        let mut pubnub = SubscribeClient::with_transport(
+            transport,
+            "ps.pndsn.com",
+            "",
+            "demo",
+            "sub-key",
+            "",
+            "agent",
+        )
+        .expect("client");
+        pubnub.set_filter_expression("type == 'a'").expect("filter");
+        pubnub.set_backfill(true);
+        pubnub.set_timetoken(Timetoken::new(100));
+
+        let message = pubnub.next_message().await.expect("live");
+        assert_eq!(message.timetoken, Timetoken::new(450));
+        let requests = requests.lock().expect("requests");
+        assert_eq!(requests.len(), 2);
+        assert!(
+            requests[1].starts_with("GET /v2/subscribe/sub-key/demo/0/500?")
+        );
+    }
+
+    #[tokio::test]
+    async fn subscribe_channel_groups_ok() {
+        let mut transport = MockTransport::new();
+        let requests = transport.requests();
+        transport.push_write_error(socket::Error::Eof);
+
+        // Channel groups only clients start without a channel
+        let mut pubnub = SubscribeClient::with_transport(
+            transport,
+            "ps.pndsn.com",
+            "",
+            "",
+            "sub-key",
+            "",
+            "agent",
+        )
+        .expect("client");
+        let error = pubnub.next_message().await.expect_err("missing");
+        assert!(matches!(error, Error::MissingChannel));
+
+        pubnub.set_channel_groups(&["group"]);
+        assert!(pubnub.next_message().await.is_err());
+        let requests = requests.lock().expect("requests");
+        assert!(requests[0].starts_with(
+            "GET /v2/subscribe/sub-key/,/0/0?channel-group=group&"
+        ));
+    }
+
+    #[tokio::test]
+    async fn subscribe_resubscribes_after_read_error() {
+        let mut transport = MockTransport::new();
+        transport.push_read_error(socket::Error::Timeout);
+        transport.push_response(http(
+            "200 OK",
+            r#"{"t":{"t":"200"},"m":[{"c":"demo","d":"hi","p":{"t":"150"}}]}"#,
+        ));
+        let requests = transport.requests();
+
+        let mut pubnub = SubscribeClient::with_transport(
+            transport,
+            "ps.pndsn.com",
+            "",
+            "demo",
+            "sub-key",
+            "",
+            "agent",
+        )
+        .expect("client");
+        let error = pubnub.next_message().await.expect_err("timeout");
+        assert!(matches!(error, Error::Socket(socket::Error::Timeout)));
+        assert!(error.is_retryable());
+
+        let message = pubnub.next_message().await.expect("message");
+        assert_eq!(message.data, "hi");
+
+        let requests = requests.lock().expect("requests");
+        assert_eq!(requests.len(), 3);
+        assert!(requests[1].contains("/demo/0/0?"));
+        assert!(requests[2].contains("/demo/0/200?"));
+    }
+
+    #[tokio::test]
+    async fn publish_ok() {
+        let mut transport = MockTransport::new();
+        transport.push_response(http("200 OK", r#"[1,"Sent","160"]"#));
+        let requests = transport.requests();
+
+        let mut pubnub = PublishClient::with_transport(
+            transport,
+            "ps.pndsn.com",
+            "",
+            "pub-key",
+            "sub-key",
+            "",
+            "agent",
+        )
+        .expect("client");
+        let timetoken = pubnub.publish("demo", r#"{"a":1}"#).await;
+        assert_eq!(timetoken.expect("timetoken"), Timetoken::new(160));
+
+        let requests = requests.lock().expect("requests");
+        assert!(requests[0].starts_with(
+            "POST /publish/pub-key/sub-key/0/demo/0\
+             ?meta=%7B%22source%22%3A%22KAFKA%22%7D&pnsdk=agent HTTP/1.1\r\n"
+        ));
+        assert!(
+            requests[0].contains("\r\nContent-Type: application/json\r\n")
+        );
+        assert!(
+            requests[0].ends_with("\r\nContent-Length: 7\r\n\r\n{\"a\":1}")
+        );
+    }
+
+    #[tokio::test]
+    async fn publish_options_ok() {
+        let mut transport = MockTransport::new();
+        transport.push_response(http("200 OK", r#"[1,"Sent","160"]"#));
+        transport.push_response(http("200 OK", r#"[1,"Sent","170"]"#));
+        let requests = transport.requests();
+
+        let mut pubnub = PublishClient::with_transport(
+            transport,
+            "ps.pndsn.com",
+            "",
+            "pub-key",
+            "sub-key",
+            "",
+            "agent",
+        )
+        .expect("client");
+        let options = PublishOptions {
+            custom_message_type: Some("telemetry".into()),
+            ..PublishOptions::fire()
         };
-        Ok(response[2].to_string())
+        pubnub
+            .publish_with_options("demo", "1", &options)
+            .await
+            .expect("fire");
+        let options: PublishOptions =
+            r#"{"store":true,"ttl":24}"#.parse().expect("options");
+        pubnub
+            .publish_with_options("demo", "2", &options)
+            .await
+            .expect("stored");
+
+        let requests = requests.lock().expect("requests");
+        assert!(requests[0].starts_with(
+            "POST /publish/pub-key/sub-key/0/demo/0\
+             ?custom_message_type=telemetry\
+             &meta=%7B%22source%22%3A%22KAFKA%22%7D\
+             &norep=true&pnsdk=agent&store=0 "
+        ));
+        assert!(requests[1].contains("&pnsdk=agent&store=1&ttl=24 "));
+
+        for options in &[
+            "[]",
+            r#"{"ttl":-1}"#,
+            r#"{"store":"no"}"#,
+            r#"{"retain":true}"#,
+            r#"{"custom_message_type":"ab"}"#,
+            r#"{"custom_message_type":"-telemetry"}"#,
+            r#"{"custom_message_type":"tele metry"}"#,
+        ] {
+            assert!(
+                matches!(
+                    options.parse::<PublishOptions>(),
+                    Err(Error::InvalidPublishOptions(_))
+                ),
+                "{}",
+                options
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn auth_key_file_ok() {
+        let path = std::env::temp_dir()
+            .join(format!("kafka-bridge-auth-{}", std::process::id()));
+        std::fs::write(&path, "token-one\n").expect("write");
+
+        let mut transport = MockTransport::new();
+        transport.push_response(http("200 OK", r#"[1,"Sent","160"]"#));
+        transport.push_response(http("403 Forbidden", r#"{"status":403}"#));
+        let requests = transport.requests();
+
+        let mut pubnub = PublishClient::with_transport(
+            transport,
+            "ps.pndsn.com",
+            "",
+            "pub-key",
+            "sub-key",
+            "",
+            "agent",
+        )
+        .expect("client");
+        pubnub.set_auth_key(Some(AuthKey::File(path.clone())));
+        pubnub.publish("demo", "1").await.expect("published");
+
+        // Rotated between requests
+        std::fs::write(&path, "token-two").expect("write");
+        let error = pubnub.publish("demo", "1").await.expect_err("denied");
+        assert!(matches!(error, Error::AccessDenied(_)));
+        assert!(!error.is_retryable());
+
+        std::fs::remove_file(&path).expect("remove");
+        let error = pubnub.publish("demo", "1").await.expect_err("missing");
+        assert!(matches!(error, Error::AuthKey(_)));
+        assert!(!error.is_retryable());
+
+        let requests = requests.lock().expect("requests").clone();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].contains("?auth=token-one&"));
+        assert!(requests[1].contains("?auth=token-two&"));
+
+        // Subscribing fails the same way, without sending a request
+        let transport = MockTransport::new();
+        let requests = transport.requests();
+        let mut pubnub = SubscribeClient::with_transport(
+            transport,
+            "ps.pndsn.com",
+            "",
+            "demo",
+            "sub-key",
+            "",
+            "agent",
+        )
+        .expect("client");
+        pubnub.set_auth_key(Some(AuthKey::File(path)));
+        let error = pubnub.next_message().await.expect_err("missing");
+        assert!(matches!(error, Error::AuthKey(_)));
+        assert!(!error.is_retryable());
+        assert!(requests.lock().expect("requests").is_empty());
+    }
+
+    #[tokio::test]
+    async fn cryptor_ok() {
+        let cryptor =
+            Cryptor::new("enigma", Mode::Legacy { random_iv: false });
+        let mut transport = MockTransport::new();
+        transport.push_response(http(
+            "200 OK",
+            r#"{"t":{"t":"100"},"m":[
+                {"c":"demo","d":"Wi24KS4pcTzvyuGOHubiXg==","p":{"t":"1"}},
+                {"c":"demo","d":"bm90IGVuY3J5cHRlZA==","p":{"t":"2"}},
+                {"c":"demo","d":"not encrypted","p":{"t":"3"}},
+                {"c":"demo","d":{"a":1},"p":{"t":"4"}}]}"#,
+        ));
+        let mut pubnub = SubscribeClient::with_transport(
+            transport,
+            "ps.pndsn.com",
+            "",
+            "demo",
+            "sub-key",
+            "",
+            "agent",
+        )
+        .expect("client");
+        pubnub.set_cryptor(Some(cryptor.clone()));
+
+        let message = pubnub.next_message().await.expect("message");
+        assert_eq!(message.data, "yay!");
+
+        // Failures are reported with the message as received
+        let event = pubnub.next_event().await.expect("event");
+        assert!(matches!(&event, Event::Undecryptable(message, _)
+            if message.data == "bm90IGVuY3J5cHRlZA=="
+                && message.timetoken == Timetoken::new(2)));
+
+        // Skipping undecryptable ones, non-string messages are plaintext
+        // kept as received
+        let message = pubnub.next_message().await.expect("message");
+        assert_eq!(message.data, r#"{"a":1}"#);
+
+        let mut transport = MockTransport::new();
+        transport.push_response(http("200 OK", r#"[1,"Sent","160"]"#));
+        let requests = transport.requests();
+        let mut pubnub = PublishClient::with_transport(
+            transport,
+            "ps.pndsn.com",
+            "",
+            "pub-key",
+            "sub-key",
+            "",
+            "agent",
+        )
+        .expect("client");
+        pubnub.set_cryptor(Some(cryptor));
+        pubnub
+            .publish("demo", r#""yay!""#)
+            .await
+            .expect("published");
+
+        let requests = requests.lock().expect("requests");
+        assert!(requests[0].ends_with(
+            "\r\nContent-Length: 26\r\n\r\n\"Wi24KS4pcTzvyuGOHubiXg==\""
+        ));
+    }
+
+    #[tokio::test]
+    async fn publish_errors() {
+        let mut transport = MockTransport::new();
+        transport
+            .push_response(http("400 Bad Request", r#"[0,"Invalid JSON"]"#));
+        transport.push_write_error(socket::Error::Eof);
+        transport.push_response(http("200 OK", "[1,"));
+
+        let mut pubnub = PublishClient::with_transport(
+            transport,
+            "ps.pndsn.com",
+            "",
+            "pub-key",
+            "sub-key",
+            "",
+            "agent",
+        )
+        .expect("client");
+
+        let error = pubnub.publish("demo", "1").await.expect_err("rejected");
+        assert!(matches!(error, Error::HTTPStatus { status: 400, .. }));
+        assert!(!error.is_retryable());
+
+        let error = pubnub.publish("demo", "1").await.expect_err("write");
+        assert!(matches!(error, Error::Socket(socket::Error::Eof)));
+
+        let error = pubnub.publish("demo", "1").await.expect_err("parse");
+        assert!(matches!(error, Error::Parse(_)));
     }
 }
@@ -0,0 +1,217 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Query parameters holding credentials.
+const SECRET_PARAMETERS: [&str; 3] = ["auth", "signature", "token"];
+
+/// Headers holding credentials.
+const SECRET_HEADERS: [&str; 2] = ["authorization", "proxy-authorization"];
+
+/// Prefixes of `PubNub` publish, subscribe and secret keys.
+const KEY_PREFIXES: [&str; 3] = ["pub-c-", "sub-c-", "sec-c-"];
+
+static LOG_PAYLOADS: AtomicBool = AtomicBool::new(false);
+
+/// Logs message payloads verbatim when `enabled`, intended for debugging
+/// only. Keys and tokens are masked either way.
+pub fn set_log_payloads(enabled: bool) {
+    LOG_PAYLOADS.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns `true` when payloads are logged verbatim.
+#[must_use]
+pub fn log_payloads() -> bool {
+    LOG_PAYLOADS.load(Ordering::Relaxed)
+}
+
+/// Masks a credential, keeping a short prefix of long values so keys
+/// can still be told apart in the logs.
+///
+/// ```
+/// use kafka_bridge::redact;
+///
+/// assert_eq!(redact::secret("sub-c-5f2a8d1e-0c1b"), "sub-c-5f***");
+/// assert_eq!(redact::secret("demo"), "***");
+/// ```
+#[must_use]
+pub fn secret(value: &str) -> String {
+    match value.get(..8) {
+        Some(prefix) if value.len() > 16 => format!("{prefix}***"),
+        _ => "***".into(),
+    }
+}
+
+/// Returns `data` when payload logging is enabled, a placeholder with its
+/// size otherwise.
+#[must_use]
+pub fn payload(data: &str) -> String {
+    payload_with(data, log_payloads())
+}
+
+/// Masks keys, tokens and the message body of a raw HTTP request.
+///
+/// ```
+/// use kafka_bridge::redact;
+///
+/// let request = redact::request(
+///     "GET /v2/subscribe/sub-c-5f2a8d1e-0c1b/demo/0/0 HTTP/1.1\r\n\r\n",
+/// );
+/// assert!(!request.contains("sub-c-5f2a8d1e-0c1b"));
+/// ```
+#[must_use]
+pub fn request(raw: &str) -> String {
+    request_with(raw, log_payloads())
+}
+
+fn payload_with(data: &str, log_payloads: bool) -> String {
+    if log_payloads || data.is_empty() {
+        data.into()
+    } else {
+        format!("[{} bytes redacted]", data.len())
+    }
+}
+
+fn request_with(raw: &str, log_payloads: bool) -> String {
+    let (head, body) = match raw.find("\r\n\r\n") {
+        Some(index) => (&raw[..index], Some(&raw[index + 4..])),
+        None => (raw, None),
+    };
+
+    let mut lines = head.split("\r\n");
+    let mut redacted = Vec::new();
+    if let Some(request_line) = lines.next() {
+        let mut parts = request_line.splitn(3, ' ');
+        let method = parts.next().unwrap_or_default();
+        let line = match (parts.next(), parts.next()) {
+            (Some(target), Some(version)) => {
+                let target = uri_with(target, log_payloads);
+                format!("{method} {target} {version}")
+            }
+            _ => request_line.into(),
+        };
+        redacted.push(line);
+    }
+    redacted.extend(lines.map(header));
+
+    let mut redacted = redacted.join("\r\n");
+    if let Some(body) = body {
+        redacted.push_str("\r\n\r\n");
+        redacted.push_str(&payload_with(body, log_payloads));
+    }
+    redacted
+}
+
+fn header(line: &str) -> String {
+    match line.find(':') {
+        Some(index)
+            if SECRET_HEADERS
+                .contains(&line[..index].trim().to_lowercase().as_str()) =>
+        {
+            format!("{}: ***", &line[..index])
+        }
+        _ => line.into(),
+    }
+}
+
+/// Masks keys and message segments in a `PubNub` path and credentials in
+/// its query string.
+fn uri_with(uri: &str, log_payloads: bool) -> String {
+    let (path, query) = match uri.find('?') {
+        Some(index) => (&uri[..index], Some(&uri[index + 1..])),
+        None => (uri, None),
+    };
+
+    let segments: Vec<&str> = path.split('/').collect();
+    let mut redacted: Vec<String> =
+        segments.iter().map(|segment| (*segment).into()).collect();
+    for (index, segment) in segments.iter().enumerate() {
+        match *segment {
+            // /publish/{pub}/{sub}/{signature}/{channel}/{callback}/{message}
+            "publish" | "signal" if index == 1 => {
+                for key in index + 1..segments.len().min(index + 4) {
+                    redacted[key] = secret(segments[key]);
+                }
+                if let Some(message) = segments.get(index + 6) {
+                    redacted[index + 6] = payload_with(message, log_payloads);
+                }
+            }
+            "subscribe" | "sub-key" | "pub-key" => {
+                if let Some(key) = segments.get(index + 1) {
+                    redacted[index + 1] = secret(key);
+                }
+            }
+            _ => {
+                if KEY_PREFIXES
+                    .iter()
+                    .any(|prefix| segment.starts_with(prefix))
+                {
+                    redacted[index] = secret(segment);
+                }
+            }
+        }
+    }
+
+    let mut redacted = redacted.join("/");
+    if let Some(query) = query {
+        let parameters: Vec<String> = query
+            .split('&')
+            .map(|parameter| match parameter.find('=') {
+                Some(index)
+                    if SECRET_PARAMETERS.contains(&&parameter[..index]) =>
+                {
+                    format!(
+                        "{}={}",
+                        &parameter[..index],
+                        secret(&parameter[index + 1..])
+                    )
+                }
+                _ => parameter.into(),
+            })
+            .collect();
+        redacted.push('?');
+        redacted.push_str(&parameters.join("&"));
+    }
+    redacted
+}
+
+#[cfg(test)]
+mod redact_tests {
+    use super::{request_with, secret};
+
+    #[test]
+    fn publish_request_ok() {
+        let raw = "GET /publish/pub-c-1111aaaa-2222/sub-c-3333bbbb-4444/0/\
+                   demo/0/%22hello%22?pnsdk=agent&auth=token-5555cccc-6666 \
+                   HTTP/1.1\r\nHost: ps.pndsn.com\r\n\r\n";
+
+        let redacted = request_with(raw, false);
+        assert!(!redacted.contains("1111aaaa"));
+        assert!(!redacted.contains("3333bbbb"));
+        assert!(!redacted.contains("5555cccc"));
+        assert!(!redacted.contains("hello"));
+        assert!(redacted.contains("/demo/0/[11 bytes redacted]?pnsdk=agent"));
+        assert!(redacted.ends_with("HTTP/1.1\r\nHost: ps.pndsn.com\r\n\r\n"));
+
+        let logged = request_with(raw, true);
+        assert!(logged.contains("%22hello%22"));
+        assert!(!logged.contains("1111aaaa"));
+    }
+
+    #[test]
+    fn subscribe_request_ok() {
+        let raw = "GET /v2/subscribe/sub-c-3333bbbb-4444/demo/0/0 HTTP/1.1\
+                   \r\nAuthorization: Bearer abc\r\n\r\n";
+        assert_eq!(
+            request_with(raw, false),
+            "GET /v2/subscribe/sub-c-33***/demo/0/0 HTTP/1.1\
+             \r\nAuthorization: ***\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn body_redacted_ok() {
+        let raw = "POST /x HTTP/1.1\r\n\r\n{\"card\":\"4111\"}";
+        assert!(request_with(raw, false).ends_with("[15 bytes redacted]"));
+        assert!(request_with(raw, true).ends_with("{\"card\":\"4111\"}"));
+        assert_eq!(secret("short"), "***");
+    }
+}
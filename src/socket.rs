@@ -1,11 +1,376 @@
-use std::io::{BufRead, BufReader, Read, Write};
-use std::net::{Shutdown, TcpStream};
-use std::{thread, time};
+use crate::proxy::Proxy;
+use crate::redact;
+use futures::future::poll_fn;
+use futures::task::noop_waker;
+use openssl::ssl::{
+    ErrorCode, HandshakeError, MidHandshakeSslStream, SslConnector,
+    SslMethod, SslStream,
+};
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use std::{fmt, io, time};
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+    BufReader,
+};
+use tokio::net::{lookup_host, TcpStream};
+use tokio::time::{delay_for, timeout, Instant};
 
+/// Socket failure with its cause.
 #[derive(Debug)]
 pub enum Error {
-    Write,
-    Read,
+    /// The host name could not be resolved.
+    Dns(String),
+    /// The connection could not be established.
+    Connect(String),
+    /// The TLS handshake or certificate verification failed.
+    Tls(String),
+    /// The peer did not answer within the socket timeout.
+    Timeout,
+    /// The peer closed the connection.
+    Eof,
+    /// More than the given number of bytes arrived for a line or body.
+    TooLarge(usize),
+    /// Writing to the connection failed.
+    Write(io::Error),
+    /// Reading from the connection failed.
+    Read(io::Error),
+}
+
+impl Error {
+    /// Returns `true` when the operation may succeed on a new connection.
+    /// TLS failures point at a certificate or configuration problem and
+    /// oversized data would arrive again, neither is retryable.
+    #[must_use]
+    pub fn is_retryable(&self) -> bool {
+        !matches!(self, Error::Tls(_) | Error::TooLarge(_))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Dns(cause) => write!(f, "DNS lookup failed: {cause}"),
+            Error::Connect(cause) => write!(f, "Connect failed: {cause}"),
+            Error::Tls(cause) => write!(f, "TLS failed: {cause}"),
+            Error::Timeout => write!(f, "Timed out"),
+            Error::Eof => write!(f, "Connection closed by peer"),
+            Error::TooLarge(max) => write!(f, "More than {max} bytes"),
+            Error::Write(cause) => write!(f, "Write failed: {cause}"),
+            Error::Read(cause) => write!(f, "Read failed: {cause}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Write(cause) | Error::Read(cause) => Some(cause),
+            _ => None,
+        }
+    }
+}
+
+/// Maps a failed read to [`Error::Eof`], [`Error::Timeout`] or
+/// [`Error::Read`].
+fn read_error(error: io::Error) -> Error {
+    match error.kind() {
+        io::ErrorKind::UnexpectedEof => Error::Eof,
+        io::ErrorKind::TimedOut => Error::Timeout,
+        _ => Error::Read(error),
+    }
+}
+
+/// Well known CA bundle locations, searched when no `ca_location` is set.
+/// The vendored `OpenSSL` build does not know where the system keeps its
+/// trusted roots, so we look for them ourselves.
+const CA_BUNDLES: &[&str] = &[
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/ca-bundle.pem",
+    "/etc/ssl/cert.pem",
+    "/usr/local/etc/openssl/cert.pem",
+];
+
+/// # TLS Configuration
+///
+/// Server certificates are always verified.
+/// Empty values fall back to their defaults.
+///
+/// * `ca_location` - PEM bundle file or hashed certificate directory used
+///   to verify the server, defaults to the system CA bundle.
+/// * `server_name` - Name sent with SNI and matched against the server
+///   certificate, defaults to the host name being connected to.
+#[derive(Clone, Debug, Default)]
+pub struct TlsConfig {
+    pub ca_location: String,
+    pub server_name: String,
+}
+
+/// # Reconnect Policy
+///
+/// Controls how a [`Socket`] retries unsuccessful connection attempts.
+/// The delay before each retry grows by `multiplier` from `initial_delay`
+/// up to `max_delay`, and is shortened by a random fraction of up to
+/// `jitter` so that many bridges don't retry in lockstep.
+///
+/// * `max_attempts` - Give up after this many attempts, `0` never gives up.
+/// * `deadline` - Give up once this much time has passed since the first
+///   attempt.
+/// * `connect_timeout` - Time allowed for each attempt, including the TLS
+///   handshake.
+///
+/// ```
+/// use kafka_bridge::socket::ReconnectPolicy;
+/// use std::time::Duration;
+///
+/// let policy = ReconnectPolicy {
+///     max_attempts: 3,
+///     ..ReconnectPolicy::default()
+/// };
+/// assert!(policy.delay(10) <= policy.max_delay);
+/// ```
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    pub initial_delay: time::Duration,
+    pub multiplier: f64,
+    pub max_delay: time::Duration,
+    pub jitter: f64,
+    pub max_attempts: u32,
+    pub deadline: Option<time::Duration>,
+    pub connect_timeout: time::Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: time::Duration::from_secs(1),
+            multiplier: 2.0,
+            max_delay: time::Duration::from_secs(30),
+            jitter: 0.25,
+            max_attempts: 10,
+            deadline: None,
+            connect_timeout: time::Duration::from_secs(10),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Delay to wait before retry number `attempt`, counting from `0`.
+    #[must_use]
+    pub fn delay(&self, attempt: u32) -> time::Duration {
+        let exponent = i32::try_from(attempt).unwrap_or(i32::MAX);
+        let backoff = self.initial_delay.as_secs_f64()
+            * self.multiplier.max(1.0).powi(exponent);
+        let capped = backoff.min(self.max_delay.as_secs_f64());
+        let jitter = self.jitter.clamp(0.0, 1.0) * random_fraction();
+        time::Duration::from_secs_f64(capped * (1.0 - jitter))
+    }
+
+    /// Returns `true` once `attempts` attempts starting at `started` have
+    /// used up the retry budget.
+    #[must_use]
+    pub fn exhausted(&self, attempts: u32, started: Instant) -> bool {
+        let attempts_exhausted =
+            self.max_attempts > 0 && attempts >= self.max_attempts;
+        let deadline_exhausted = self
+            .deadline
+            .is_some_and(|deadline| started.elapsed() >= deadline);
+        attempts_exhausted || deadline_exhausted
+    }
+}
+
+/// Random value in `[0, 1]`.
+fn random_fraction() -> f64 {
+    let mut bytes = [0_u8; 4];
+    if openssl::rand::rand_bytes(&mut bytes).is_err() {
+        return 0.5;
+    }
+    f64::from(u32::from_le_bytes(bytes)) / f64::from(u32::MAX)
+}
+
+// =-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=
+// Async TLS Stream
+//
+// OpenSSL drives a blocking `Read + Write` stream. `Bridge` polls the
+// async TCP stream with the waker of the task currently polling the
+// `TlsStream`, turning `Poll::Pending` into `WouldBlock` for OpenSSL and
+// back into `Poll::Pending` for tokio.
+// =-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=
+#[derive(Debug)]
+struct Bridge {
+    stream: TcpStream,
+    waker: Option<Waker>,
+}
+
+impl Bridge {
+    fn poll<T>(
+        &mut self,
+        operation: impl FnOnce(
+            Pin<&mut TcpStream>,
+            &mut Context<'_>,
+        ) -> Poll<io::Result<T>>,
+    ) -> io::Result<T> {
+        let waker = self.waker.clone().unwrap_or_else(noop_waker);
+        let mut context = Context::from_waker(&waker);
+        match operation(Pin::new(&mut self.stream), &mut context) {
+            Poll::Ready(result) => result,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl Read for Bridge {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.poll(|stream, context| stream.poll_read(context, buf))
+    }
+}
+
+impl Write for Bridge {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.poll(|stream, context| stream.poll_write(context, buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.poll(AsyncWrite::poll_flush)
+    }
+}
+
+struct TlsStream(SslStream<Bridge>);
+
+impl TlsStream {
+    fn with_waker<T>(
+        &mut self,
+        context: &Context<'_>,
+        operation: impl FnOnce(&mut SslStream<Bridge>) -> io::Result<T>,
+    ) -> Poll<io::Result<T>> {
+        self.0.get_mut().waker = Some(context.waker().clone());
+        let result = operation(&mut self.0);
+        self.0.get_mut().waker = None;
+        match result {
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                Poll::Pending
+            }
+            result => Poll::Ready(result),
+        }
+    }
+}
+
+impl AsyncRead for TlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        context: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .with_waker(context, |stream| stream.read(buf))
+    }
+}
+
+impl AsyncWrite for TlsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        context: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .with_waker(context, |stream| stream.write(buf))
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        context: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.get_mut().with_waker(context, Write::flush)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        context: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let result =
+            this.with_waker(context, |stream| match stream.shutdown() {
+                Ok(_) => Ok(()),
+                Err(error) if error.code() == ErrorCode::ZERO_RETURN => {
+                    Ok(())
+                }
+                Err(error) => Err(error
+                    .into_io_error()
+                    .unwrap_or_else(io::Error::other)),
+            });
+        match result {
+            Poll::Ready(Ok(())) => {
+                Pin::new(&mut this.0.get_mut().stream).poll_shutdown(context)
+            }
+            result => result,
+        }
+    }
+}
+
+enum Stream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream>),
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        context: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_read(context, buf),
+            Stream::Tls(stream) => {
+                Pin::new(stream.as_mut()).poll_read(context, buf)
+            }
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        context: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => {
+                Pin::new(stream).poll_write(context, buf)
+            }
+            Stream::Tls(stream) => {
+                Pin::new(stream.as_mut()).poll_write(context, buf)
+            }
+        }
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        context: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_flush(context),
+            Stream::Tls(stream) => {
+                Pin::new(stream.as_mut()).poll_flush(context)
+            }
+        }
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        context: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_shutdown(context),
+            Stream::Tls(stream) => {
+                Pin::new(stream.as_mut()).poll_shutdown(context)
+            }
+        }
+    }
 }
 
 pub struct Socket {
@@ -13,8 +378,10 @@ pub struct Socket {
     agent: String,
     connected: bool,
     timeout: u64,
-    stream: TcpStream,
-    reader: BufReader<TcpStream>,
+    tls: Option<TlsConfig>,
+    policy: ReconnectPolicy,
+    proxy: Option<Proxy>,
+    reader: Option<BufReader<Stream>>,
 }
 
 pub fn log(host: &str, agent: &str, info: &str) {
@@ -31,6 +398,7 @@ pub fn log(host: &str, agent: &str, info: &str) {
 /// # Socket
 ///
 /// The user interface for this library.
+/// The connection is opened on first use and reopened after failures.
 ///
 /// ```no_run
 /// use kafka_bridge::socket::Socket;
@@ -38,30 +406,89 @@ pub fn log(host: &str, agent: &str, info: &str) {
 /// let host = "pubsub.pubnub.com:80";
 /// let mut socket = Socket::new(host, "HTTP Agent", 5);
 /// ```
+///
+/// Encrypted connections are made with [`Socket::new_with_tls`].
+///
+/// ```no_run
+/// use kafka_bridge::socket::{Socket, TlsConfig};
+///
+/// let host = "pubsub.pubnub.com:443";
+/// let tls = TlsConfig::default();
+/// let mut socket = Socket::new_with_tls(host, "HTTP Agent", 5, &tls);
+/// ```
 impl Socket {
     #[must_use]
     pub fn new(host: &str, agent: &str, timeout: u64) -> Self {
-        let stream = Socket::connect(host, agent, timeout);
         Self {
             host: host.into(),
             agent: agent.into(),
             timeout,
-            connected: true,
-            stream: stream.try_clone().expect("Unable to clone stream"),
-            reader: BufReader::new(stream),
+            connected: false,
+            tls: None,
+            policy: ReconnectPolicy::default(),
+            proxy: None,
+            reader: None,
         }
     }
 
+    /// Creates a new [`Socket`] which encrypts all traffic with TLS.
+    /// The server certificate and host name are verified on connect.
+    #[must_use]
+    pub fn new_with_tls(
+        host: &str,
+        agent: &str,
+        timeout: u64,
+        tls: &TlsConfig,
+    ) -> Self {
+        Self {
+            host: host.into(),
+            agent: agent.into(),
+            timeout,
+            connected: false,
+            tls: Some(tls.clone()),
+            policy: ReconnectPolicy::default(),
+            proxy: None,
+            reader: None,
+        }
+    }
+
+    /// Returns `true` when the connection is encrypted with TLS.
+    #[must_use]
+    pub fn is_tls(&self) -> bool {
+        self.tls.is_some()
+    }
+
+    /// Replaces the [`ReconnectPolicy`] used for following connects.
+    pub fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+        self.policy = policy;
+    }
+
+    /// Tunnels following connects through `proxy`, `None` connects
+    /// directly.
+    pub fn set_proxy(&mut self, proxy: Option<Proxy>) {
+        self.proxy = proxy;
+    }
+
     pub fn log(&mut self, message: &str) {
         log(&self.host, &self.agent, message);
     }
 
-    pub fn check_reconnect(&mut self) {
+    /// Connects if not connected.
+    ///
+    /// # Errors
+    ///
+    /// This function returns the last connect failure, [`Error::Dns`],
+    /// [`Error::Connect`], [`Error::Tls`] or [`Error::Timeout`], once the
+    /// [`ReconnectPolicy`] gives up.
+    pub async fn check_reconnect(&mut self) -> Result<(), Error> {
         if self.connected {
-            return;
+            return Ok(());
+        }
+        if self.reader.is_some() {
+            self.reconnect().await
+        } else {
+            self.connect(0).await
         }
-        self.reconnect();
-        self.connected = true;
     }
 
     /// ## Write Data
@@ -70,37 +497,89 @@ impl Socket {
     ///
     /// ```no_run
     /// use kafka_bridge::socket::Socket;
-    /// let host = "pubsub.pubnub.com:80";
-    /// let mut socket = Socket::new(host, "HTTP Agent", 5);
-    /// let request = "GET / HTTP/1.1\r\nHost: pubnub.com\r\n\r\n";
-    /// socket.write(request).expect("data written");
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let host = "pubsub.pubnub.com:80";
+    ///     let mut socket = Socket::new(host, "HTTP Agent", 5);
+    ///     let request = "GET / HTTP/1.1\r\nHost: pubnub.com\r\n\r\n";
+    ///     socket.write(request).await.expect("data written");
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// * [`Error::Write`] on unsuccessful write
+    /// * [`Error::Timeout`] when the write doesn't finish in time
+    /// * the connect failure when the connection can't be established
+    pub async fn write(
+        &mut self,
+        data: impl AsRef<str>,
+    ) -> Result<usize, Error> {
+        let data = data.as_ref().as_bytes();
+        self.write_all(data).await?;
+        Ok(data.len())
+    }
+
+    /// ## Write All Bytes
+    ///
+    /// Write every byte of `data` to the stream, retrying partial writes.
+    ///
+    /// ```no_run
+    /// use kafka_bridge::socket::Socket;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let host = "pubsub.pubnub.com:80";
+    ///     let mut socket = Socket::new(host, "HTTP Agent", 5);
+    ///     let request = b"GET / HTTP/1.1\r\nHost: pubnub.com\r\n\r\n";
+    ///     socket.write_all(request).await.expect("data written");
+    /// }
     /// ```
     ///
     /// # Errors
     ///
-    /// This function can return [`Error::Write`] on unsuccessful write.
-    pub fn write(&mut self, data: impl AsRef<str>) -> Result<usize, Error> {
+    /// * [`Error::Write`] on unsuccessful write
+    /// * [`Error::Timeout`] when the write doesn't finish in time
+    /// * the connect failure when the connection can't be established
+    pub async fn write_all(
+        &mut self,
+        data: impl AsRef<[u8]>,
+    ) -> Result<(), Error> {
         // Reconnect if not connected
-        self.check_reconnect();
+        self.check_reconnect().await?;
 
         // Log Write Output
-        self.log(data.as_ref());
+        let data = data.as_ref();
+        self.log(&redact::request(&String::from_utf8_lossy(data)));
 
-        let result = self.stream.write(data.as_ref().as_bytes());
-        match result {
-            Ok(size) => {
-                if size > 0 {
-                    return Ok(size);
+        let duration = time::Duration::from_secs(self.timeout);
+        let result = match self.reader.as_mut() {
+            Some(reader) => {
+                let stream = reader.get_mut();
+                let write = async {
+                    stream.write_all(data).await?;
+                    stream.flush().await
+                };
+                match timeout(duration, write).await {
+                    Ok(result) => result,
+                    Err(elapsed) => {
+                        Err(io::Error::new(io::ErrorKind::TimedOut, elapsed))
+                    }
                 }
-                self.log("No data has been written.");
-                self.connected = false;
-                Err(Error::Write)
             }
+            None => Err(io::ErrorKind::NotConnected.into()),
+        };
+        match result {
+            Ok(()) => Ok(()),
             Err(error) => {
-                self.log(&format!("Unwrittable: {}", error));
-                self.log(&format!("Disconnected: {}", error));
+                self.log(&format!("Unwrittable: {error}"));
+                self.log(&format!("Disconnected: {error}"));
                 self.connected = false;
-                Err(Error::Write)
+                match error.kind() {
+                    io::ErrorKind::TimedOut => Err(Error::Timeout),
+                    _ => Err(Error::Write(error)),
+                }
             }
         }
     }
@@ -108,161 +587,666 @@ impl Socket {
     /// ## Read Line
     ///
     /// Read a line of data from the stream.
+    /// Invalid UTF-8 sequences are replaced, use [`Socket::read_exact`]
+    /// for binary data.
+    ///
+    /// ```no_run
+    /// use kafka_bridge::socket::Socket;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let host = "pubsub.pubnub.com:80";
+    ///     let mut socket = Socket::new(host.into(), "HTTP Agent", 5);
+    ///     let request = "GET / HTTP/1.1\r\nHost: pubnub.com\r\n\r\n";
+    ///     socket.write(request).await;
+    ///     let line = socket.readln().await;
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// * [`Error::Read`] on unsuccessful read
+    /// * [`Error::Eof`] when the peer closed the connection
+    /// * [`Error::Timeout`] when no line arrives in time
+    /// * the connect failure when the connection can't be established
+    pub async fn readln(&mut self) -> Result<String, Error> {
+        self.readln_max(usize::MAX).await
+    }
+
+    /// ## Read Bounded Line
+    ///
+    /// Read a line of data from the stream, giving up once `max` bytes
+    /// arrived without a line end.
     ///
     /// ```no_run
     /// use kafka_bridge::socket::Socket;
-    /// let host = "pubsub.pubnub.com:80";
-    /// let mut socket = Socket::new(host.into(), "HTTP Agent", 5);
-    /// let request = "GET / HTTP/1.1\r\nHost: pubnub.com\r\n\r\n";
-    /// socket.write(request);
-    /// let line = socket.readln();
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let host = "pubsub.pubnub.com:80";
+    ///     let mut socket = Socket::new(host.into(), "HTTP Agent", 5);
+    ///     let request = "GET / HTTP/1.1\r\nHost: pubnub.com\r\n\r\n";
+    ///     socket.write(request).await;
+    ///     let status_line = socket.readln_max(8192).await;
+    /// }
     /// ```
     ///
     /// # Errors
     ///
-    /// This function can return [`Error::Read`] on unsuccessful read.
-    pub fn readln(&mut self) -> Result<String, Error> {
+    /// * [`Error::TooLarge`] when the line is longer than `max` bytes
+    /// * [`Error::Read`] on unsuccessful read
+    /// * [`Error::Eof`] when the peer closed the connection
+    /// * [`Error::Timeout`] when no line arrives in time
+    /// * the connect failure when the connection can't be established
+    pub async fn readln_max(&mut self, max: usize) -> Result<String, Error> {
         // Reconnect if not connected
-        self.check_reconnect();
+        self.check_reconnect().await?;
 
-        let mut line = String::new();
-        let size = match self.reader.read_line(&mut line) {
-            Ok(size) => size,
-            Err(_error) => {
-                self.connected = false;
-                return Err(Error::Read);
+        let duration = time::Duration::from_secs(self.timeout);
+        let limit = u64::try_from(max).unwrap_or(u64::MAX);
+        let mut line = Vec::new();
+        let result = match self.reader.as_mut() {
+            Some(reader) => timeout(
+                duration,
+                reader.take(limit).read_until(b'\n', &mut line),
+            )
+            .await
+            .unwrap_or_else(|elapsed| {
+                Err(io::Error::new(io::ErrorKind::TimedOut, elapsed))
+            }),
+            None => Ok(0),
+        };
+        let result = match result {
+            Ok(size) if size == max && !line.ends_with(b"\n") => {
+                Err(Error::TooLarge(max))
             }
+            Ok(0) => Err(Error::Eof),
+            Ok(_size) => Ok(()),
+            Err(error) => Err(read_error(error)),
         };
-
-        if size == 0 {
+        if let Err(error) = result {
             self.connected = false;
-            return Err(Error::Read);
+            return Err(error);
         }
 
-        Ok(line)
+        Ok(String::from_utf8_lossy(&line).into_owned())
+    }
+
+    /// ## Read Text
+    ///
+    /// Read exactly `bytes` bytes of text from the stream.
+    /// Invalid UTF-8 sequences are replaced, use [`Socket::read_exact`]
+    /// for binary data.
+    ///
+    /// ```no_run
+    /// use kafka_bridge::socket::Socket;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let host = "pubsub.pubnub.com:80";
+    ///     let mut socket = Socket::new(host.into(), "HTTP Agent", 5);
+    ///     let request = "GET / HTTP/1.1\r\nHost: pubnub.com\r\n\r\n";
+    ///     socket.write(request).await.expect("data written");
+    ///     let data = socket.read(30).await.expect("data read"); // 30 bytes
+    ///     println!("{}", data);
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// * [`Error::Read`] on unsuccessful read
+    /// * [`Error::Eof`] when the stream ends early
+    /// * [`Error::Timeout`] when the bytes don't arrive in time
+    /// * the connect failure when the connection can't be established
+    pub async fn read(&mut self, bytes: usize) -> Result<String, Error> {
+        let data = self.read_exact(bytes).await?;
+        Ok(String::from_utf8_lossy(&data).into_owned())
     }
 
     /// ## Read Bytes
     ///
-    /// Read specified amount of data from the stream.
+    /// Read exactly `bytes` bytes from the stream, waiting for more data
+    /// until all bytes have arrived.
     ///
     /// ```no_run
     /// use kafka_bridge::socket::Socket;
     ///
-    /// let host = "pubsub.pubnub.com:80";
-    /// let mut socket = Socket::new(host.into(), "HTTP Agent", 5);
-    /// let request = "GET / HTTP/1.1\r\nHost: pubnub.com\r\n\r\n";
-    /// socket.write(request).expect("data written");
-    /// let data = socket.read(30).expect("data read"); // read 30 bytes
-    /// println!("{}", data);
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let host = "pubsub.pubnub.com:80";
+    ///     let mut socket = Socket::new(host.into(), "HTTP Agent", 5);
+    ///     let request = "GET / HTTP/1.1\r\nHost: pubnub.com\r\n\r\n";
+    ///     socket.write(request).await.expect("data written");
+    ///     let data = socket.read_exact(30).await.expect("data read");
+    ///     assert_eq!(data.len(), 30);
+    /// }
     /// ```
     ///
     /// # Errors
     ///
-    /// This function can return [`Error::Read`] on unsuccessful read.
-    pub fn read(&mut self, bytes: usize) -> Result<String, Error> {
+    /// * [`Error::Read`] on unsuccessful read
+    /// * [`Error::Eof`] when the stream ends early
+    /// * [`Error::Timeout`] when the bytes don't arrive in time
+    /// * the connect failure when the connection can't be established
+    pub async fn read_exact(
+        &mut self,
+        bytes: usize,
+    ) -> Result<Vec<u8>, Error> {
         // Reconnect if not connected
-        self.check_reconnect();
+        self.check_reconnect().await?;
 
+        let duration = time::Duration::from_secs(self.timeout);
         let mut buffer = vec![0_u8; bytes];
-        let size = match self.reader.read(&mut buffer) {
-            Ok(size) => size,
-            Err(_error) => {
+        let result = match self.reader.as_mut() {
+            Some(reader) => timeout(duration, reader.read_exact(&mut buffer))
+                .await
+                .unwrap_or_else(|elapsed| {
+                    Err(io::Error::new(io::ErrorKind::TimedOut, elapsed))
+                }),
+            None => Err(io::ErrorKind::NotConnected.into()),
+        };
+        match result {
+            Ok(_size) => Ok(buffer),
+            Err(error) => {
                 self.connected = false;
-                return Err(Error::Read);
+                Err(read_error(error))
+            }
+        }
+    }
+
+    /// ## Read To End
+    ///
+    /// Read until the peer closes the connection, for close-delimited
+    /// HTTP bodies, giving up after `max` bytes.
+    ///
+    /// ```no_run
+    /// use kafka_bridge::socket::Socket;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let host = "pubsub.pubnub.com:80";
+    ///     let mut socket = Socket::new(host.into(), "HTTP Agent", 5);
+    ///     let request = "GET / HTTP/1.0\r\nHost: pubnub.com\r\n\r\n";
+    ///     socket.write(request).await.expect("data written");
+    ///     let data = socket.read_to_end(65536).await.expect("data read");
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// * [`Error::TooLarge`] when more than `max` bytes arrive
+    /// * [`Error::Read`] on unsuccessful read
+    /// * [`Error::Timeout`] when the peer doesn't close in time
+    /// * the connect failure when the connection can't be established
+    pub async fn read_to_end(
+        &mut self,
+        max: usize,
+    ) -> Result<Vec<u8>, Error> {
+        // Reconnect if not connected
+        self.check_reconnect().await?;
+
+        // Read one byte past the limit to tell a full body from a cut one
+        let duration = time::Duration::from_secs(self.timeout);
+        let limit = u64::try_from(max).unwrap_or(u64::MAX).saturating_add(1);
+        let mut buffer = Vec::new();
+        let result = match self.reader.as_mut() {
+            Some(reader) => {
+                timeout(duration, reader.take(limit).read_to_end(&mut buffer))
+                    .await
+                    .unwrap_or_else(|elapsed| {
+                        Err(io::Error::new(io::ErrorKind::TimedOut, elapsed))
+                    })
             }
+            None => Err(io::ErrorKind::NotConnected.into()),
         };
 
-        if size == 0 {
-            self.connected = false;
-            return Err(Error::Read);
+        // The connection is used up either way
+        self.disconnect().await;
+        match result {
+            Ok(size) if size > max => Err(Error::TooLarge(max)),
+            Ok(_size) => Ok(buffer),
+            Err(error) => Err(read_error(error)),
         }
-
-        Ok(String::from_utf8_lossy(&buffer).to_string())
     }
 
     /// ## Disconnect
     ///
     /// This will courteously turn off the connection of your socket.
+    /// The next use connects again without waiting for the
+    /// [`ReconnectPolicy`].
     ///
     /// ```no_run
     /// use kafka_bridge::socket::Socket;
-    /// let host = "pubsub.pubnub.com:80";
-    /// let mut socket = Socket::new(host.into(), "HTTP Agent", 5);
-    /// socket.disconnect();
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let host = "pubsub.pubnub.com:80";
+    ///     let mut socket = Socket::new(host.into(), "HTTP Agent", 5);
+    ///     socket.disconnect().await;
+    /// }
     /// ```
-    pub fn disconnect(&mut self) {
-        self.stream.shutdown(Shutdown::Both).unwrap_or_default();
+    pub async fn disconnect(&mut self) {
+        if let Some(reader) = self.reader.as_mut() {
+            let duration = time::Duration::from_secs(self.timeout);
+            let _ = timeout(duration, reader.get_mut().shutdown()).await;
+        }
+        self.connected = false;
+        self.reader = None;
     }
 
-    pub fn reconnect(&mut self) {
-        thread::sleep(time::Duration::new(1, 0));
+    /// Reopens a lost connection after the first [`ReconnectPolicy`]
+    /// delay.
+    ///
+    /// # Errors
+    ///
+    /// This function returns the last connect failure once the
+    /// [`ReconnectPolicy`] gives up.
+    pub async fn reconnect(&mut self) -> Result<(), Error> {
         self.log("Reconnecting");
-        let stream = Socket::connect(&self.host, &self.agent, self.timeout);
+        self.connect(1).await
+    }
+
+    /// Connects, retrying per the [`ReconnectPolicy`].
+    /// A `first_delay` of `1` waits the first backoff delay before
+    /// connecting, `0` connects immediately.
+    async fn connect(&mut self, first_delay: u32) -> Result<(), Error> {
+        let started = Instant::now();
+        let mut attempts = 0;
+        let stream = loop {
+            if attempts + first_delay > 0 {
+                let delay = self.policy.delay(attempts + first_delay - 1);
+                delay_for(delay).await;
+            }
+            attempts += 1;
+
+            let error =
+                match timeout(self.policy.connect_timeout, self.open()).await
+                {
+                    Ok(Ok(stream)) => break stream,
+                    Ok(Err(error)) => error,
+                    Err(_elapsed) => Error::Timeout,
+                };
+            self.log(&format!("{error}"));
+
+            // Retry connection until the policy gives up
+            if self.policy.exhausted(attempts, started) {
+                self.log(&format!("Giving up after {attempts} attempts"));
+                self.connected = false;
+                return Err(error);
+            }
+        };
         self.connected = true;
-        self.stream = stream.try_clone().expect("Unable to clone stream");
-        self.reader = BufReader::new(stream);
-    }
-
-    fn connect(ip_port: &str, agent: &str, timeout: u64) -> TcpStream {
-        loop {
-            let host: String = ip_port.into();
-            let error = match TcpStream::connect(host) {
-                Ok(stream) => {
-                    log(ip_port, agent, "Connected");
-                    stream
-                        .set_read_timeout(Some(time::Duration::new(
-                            timeout, 0,
-                        )))
-                        .expect("Set Socket Read Timeout");
-                    stream
-                        .set_write_timeout(Some(time::Duration::new(
-                            timeout, 0,
-                        )))
-                        .expect("Set Socket Write Timeout");
-                    return stream;
-                }
-                Err(error) => error,
-            };
+        self.reader = Some(BufReader::new(stream));
+        Ok(())
+    }
 
-            // Retry connection until the host becomes available
-            log(ip_port, agent, &format!("{}", error));
-            thread::sleep(time::Duration::new(1, 0));
+    async fn open(&mut self) -> Result<Stream, Error> {
+        let stream = match self.proxy.as_ref() {
+            Some(proxy) => {
+                proxy.connect(&self.host).await.map_err(Error::Connect)?
+            }
+            None => dial(&self.host).await?,
+        };
+        match self.tls.as_ref() {
+            None => {
+                self.log("Connected");
+                Ok(Stream::Plain(stream))
+            }
+            Some(tls) => {
+                let stream = handshake(&self.host, tls, stream)
+                    .await
+                    .map_err(Error::Tls)?;
+                self.log("Connected with TLS");
+                Ok(Stream::Tls(Box::new(stream)))
+            }
         }
     }
 }
 
+/// Resolves `ip_port` and connects to the first reachable address,
+/// telling DNS failures apart from refused connections.
+async fn dial(ip_port: &str) -> Result<TcpStream, Error> {
+    let addresses: Vec<SocketAddr> = lookup_host(ip_port)
+        .await
+        .map_err(|error| Error::Dns(format!("{error}")))?
+        .collect();
+    if addresses.is_empty() {
+        return Err(Error::Dns(format!("No addresses for {ip_port}")));
+    }
+    TcpStream::connect(&addresses[..])
+        .await
+        .map_err(|error| Error::Connect(format!("{error}")))
+}
+
+/// Host name part of `host:port`.
+fn host_name(ip_port: &str) -> &str {
+    match ip_port.rfind(':') {
+        Some(index) => &ip_port[..index],
+        None => ip_port,
+    }
+}
+
+async fn handshake(
+    ip_port: &str,
+    tls: &TlsConfig,
+    stream: TcpStream,
+) -> Result<TlsStream, String> {
+    let mut builder = SslConnector::builder(SslMethod::tls_client())
+        .map_err(|error| format!("TLS Initialize: {error}"))?;
+
+    let ca_location = if tls.ca_location.is_empty() {
+        CA_BUNDLES
+            .iter()
+            .find(|bundle| Path::new(bundle).is_file())
+            .map_or("", |bundle| *bundle)
+    } else {
+        &tls.ca_location
+    };
+    let loaded = if ca_location.is_empty() {
+        Ok(())
+    } else if Path::new(ca_location).is_dir() {
+        builder.load_verify_locations(None, Some(Path::new(ca_location)))
+    } else {
+        builder.set_ca_file(ca_location)
+    };
+    loaded.map_err(|error| {
+        format!("TLS CA Location '{ca_location}': {error}")
+    })?;
+
+    let server_name = if tls.server_name.is_empty() {
+        host_name(ip_port)
+    } else {
+        &tls.server_name
+    };
+    let connector = builder.build();
+
+    // Drive the handshake, every poll hands the task waker to `Bridge`
+    let mut start = Some(Bridge {
+        stream,
+        waker: None,
+    });
+    let mut pending: Option<MidHandshakeSslStream<Bridge>> = None;
+    poll_fn(|context| {
+        let result = match (start.take(), pending.take()) {
+            (Some(mut bridge), _) => {
+                bridge.waker = Some(context.waker().clone());
+                connector.connect(server_name, bridge)
+            }
+            (None, Some(mut stream)) => {
+                stream.get_mut().waker = Some(context.waker().clone());
+                stream.handshake()
+            }
+            (None, None) => {
+                return Poll::Ready(Err("TLS Handshake: Aborted".into()))
+            }
+        };
+        match result {
+            Ok(mut stream) => {
+                stream.get_mut().waker = None;
+                Poll::Ready(Ok(TlsStream(stream)))
+            }
+            Err(HandshakeError::WouldBlock(stream)) => {
+                pending = Some(stream);
+                Poll::Pending
+            }
+            Err(error) => Poll::Ready(Err(format!("TLS Handshake: {error}"))),
+        }
+    })
+    .await
+}
+
 #[cfg(test)]
 mod socket_tests {
-    use super::{Socket};
+    use super::{host_name, Error, ReconnectPolicy, Socket, TlsConfig};
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::ssl::{SslAcceptor, SslMethod};
+    use openssl::x509::extension::SubjectAlternativeName;
+    use openssl::x509::{X509NameBuilder, X509};
+    use std::io::{BufRead, BufReader, Write};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
 
-    #[test]
-    fn write_ok() {
-        let host = "www.pubnub.com:80";
-        let mut socket = Socket::new(host, "HTTP Agent", 5);
+    /// Local server answering one request with an empty `200`, returns
+    /// its address.
+    async fn http_server() -> String {
+        let mut listener =
+            TcpListener::bind("127.0.0.1:0").await.expect("listener");
+        let host = listener.local_addr().expect("address").to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.expect("accept");
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                request.push(stream.read_u8().await.expect("request"));
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .expect("response");
+        });
+        host
+    }
+
+    /// Self-signed certificate of `localhost` and its key.
+    fn self_signed() -> (X509, PKey<Private>) {
+        let group =
+            EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).expect("curve");
+        let key = PKey::from_ec_key(EcKey::generate(&group).expect("key"))
+            .expect("key");
+        let mut name = X509NameBuilder::new().expect("name");
+        name.append_entry_by_text("CN", "localhost").expect("name");
+        let name = name.build();
+
+        let mut builder = X509::builder().expect("certificate");
+        builder.set_version(2).expect("version");
+        let serial = BigNum::from_u32(1).expect("serial");
+        builder
+            .set_serial_number(&serial.to_asn1_integer().expect("serial"))
+            .expect("serial");
+        builder.set_subject_name(&name).expect("subject");
+        builder.set_issuer_name(&name).expect("issuer");
+        builder.set_pubkey(&key).expect("public key");
+        let now = Asn1Time::days_from_now(0).expect("time");
+        builder.set_not_before(&now).expect("not before");
+        let later = Asn1Time::days_from_now(1).expect("time");
+        builder.set_not_after(&later).expect("not after");
+        let names = SubjectAlternativeName::new()
+            .dns("localhost")
+            .build(&builder.x509v3_context(None, None))
+            .expect("names");
+        builder.append_extension(names).expect("names");
+        builder.sign(&key, MessageDigest::sha256()).expect("signed");
+        (builder.build(), key)
+    }
+
+    #[tokio::test]
+    async fn write_ok() {
+        let host = http_server().await;
+        let mut socket = Socket::new(&host, "HTTP Agent", 5);
 
         let request = "GET / HTTP/1.1\r\nHost: pubnub.com\r\n\r\n";
-        let _ = socket.write(request).expect("data written");
+        let _ = socket.write(request).await.expect("data written");
     }
 
-    #[test]
-    fn read_ok() {
-        let host = "www.pubnub.com:80";
-        let mut socket = Socket::new(host, "HTTP Agent", 5);
+    #[tokio::test]
+    async fn read_ok() {
+        let host = http_server().await;
+        let mut socket = Socket::new(&host, "HTTP Agent", 5);
 
         let request = "GET / HTTP/1.1\r\nHost: pubnub.com\r\n\r\n";
-        socket.write(request).expect("data written");
+        socket.write(request).await.expect("data written");
 
-        let result = socket.readln();
+        let result = socket.readln().await;
         assert!(result.is_ok());
 
         let data = result.expect("data");
         assert!(!data.is_empty());
 
-        let result = socket.readln();
+        let result = socket.readln().await;
         assert!(result.is_ok());
 
         let data = result.expect("data");
         assert!(!data.is_empty());
     }
+
+    #[test]
+    fn host_name_ok() {
+        assert_eq!(host_name("ps.pndsn.com:443"), "ps.pndsn.com");
+        assert_eq!(host_name("ps.pndsn.com"), "ps.pndsn.com");
+    }
+
+    #[tokio::test]
+    async fn tls_read_ok() {
+        let (certificate, key) = self_signed();
+        let ca = std::env::temp_dir()
+            .join(format!("kafka-bridge-ca-{}.pem", std::process::id()));
+        std::fs::write(&ca, certificate.to_pem().expect("pem")).expect("ca");
+
+        // Blocking TLS server on its own thread
+        let mut acceptor =
+            SslAcceptor::mozilla_intermediate(SslMethod::tls()).expect("tls");
+        acceptor.set_private_key(&key).expect("key");
+        acceptor.set_certificate(&certificate).expect("certificate");
+        let acceptor = acceptor.build();
+        let listener =
+            std::net::TcpListener::bind("127.0.0.1:0").expect("listener");
+        let host = listener.local_addr().expect("address").to_string();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("accept");
+            let stream = acceptor.accept(stream).expect("handshake");
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            while line != "\r\n" {
+                line.clear();
+                reader.read_line(&mut line).expect("request");
+            }
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .expect("response");
+        });
+
+        let tls = TlsConfig {
+            ca_location: ca.to_string_lossy().into(),
+            server_name: "localhost".into(),
+        };
+        let mut socket = Socket::new_with_tls(&host, "HTTP Agent", 5, &tls);
+        assert!(socket.is_tls());
+
+        let request = "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        socket.write(request).await.expect("data written");
+
+        let data = socket.readln().await.expect("data");
+        assert!(data.starts_with("HTTP/1.1"));
+        server.join().expect("server");
+        std::fs::remove_file(&ca).expect("remove");
+    }
+
+    #[test]
+    fn reconnect_policy_delay_ok() {
+        let policy = ReconnectPolicy {
+            jitter: 0.0,
+            ..ReconnectPolicy::default()
+        };
+        assert_eq!(policy.delay(0), Duration::from_secs(1));
+        assert_eq!(policy.delay(3), Duration::from_secs(8));
+        assert_eq!(policy.delay(100), policy.max_delay);
+
+        let policy = ReconnectPolicy::default();
+        for attempt in 0..10 {
+            let delay = policy.delay(attempt);
+            assert!(delay <= policy.max_delay);
+            assert!(delay >= Duration::from_millis(750));
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnect_gives_up() {
+        // Nothing listens on port 9 (discard) of the loopback interface
+        let mut socket = Socket::new("127.0.0.1:9", "HTTP Agent", 5);
+        socket.set_reconnect_policy(ReconnectPolicy {
+            initial_delay: Duration::from_millis(1),
+            max_attempts: 3,
+            ..ReconnectPolicy::default()
+        });
+
+        let result = socket.write("GET / HTTP/1.1\r\n\r\n").await;
+        assert!(matches!(result, Err(Error::Connect(_))));
+    }
+
+    #[tokio::test]
+    async fn read_exact_ok() {
+        let mut listener =
+            TcpListener::bind("127.0.0.1:0").await.expect("listener");
+        let host = listener.local_addr().expect("address").to_string();
+
+        // Multi-byte UTF-8 split across writes
+        let payload = "\u{1f600} héllo wörld \u{1f680}".as_bytes().to_vec();
+        let expected = payload.clone();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.expect("accept");
+            for chunk in payload.chunks(3) {
+                stream.write_all(chunk).await.expect("chunk written");
+                stream.flush().await.expect("chunk flushed");
+                tokio::time::delay_for(Duration::from_millis(5)).await;
+            }
+        });
+
+        let mut socket = Socket::new(&host, "HTTP Agent", 5);
+        let data = socket.read_exact(expected.len()).await.expect("data");
+        assert_eq!(data, expected);
+
+        // Stream ends before more bytes arrive
+        server.await.expect("server");
+        assert!(matches!(socket.read_exact(1).await, Err(Error::Eof)));
+    }
+
+    #[tokio::test]
+    async fn write_all_ok() {
+        let mut listener =
+            TcpListener::bind("127.0.0.1:0").await.expect("listener");
+        let host = listener.local_addr().expect("address").to_string();
+
+        let payload = vec![0xff_u8; 4 * 1024 * 1024];
+        let expected = payload.len();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.expect("accept");
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).await.expect("received");
+            received
+        });
+
+        let mut socket = Socket::new(&host, "HTTP Agent", 5);
+        socket.write_all(&payload).await.expect("data written");
+        socket.disconnect().await;
+
+        let received = server.await.expect("server");
+        assert_eq!(received.len(), expected);
+    }
+
+    #[tokio::test]
+    async fn readln_max_ok() {
+        let mut listener =
+            TcpListener::bind("127.0.0.1:0").await.expect("listener");
+        let host = listener.local_addr().expect("address").to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.expect("accept");
+            stream
+                .write_all(b"short\nmuch longer line\n")
+                .await
+                .expect("lines");
+        });
+
+        let mut socket = Socket::new(&host, "HTTP Agent", 5);
+        assert_eq!(socket.readln_max(6).await.expect("line"), "short\n");
+        assert!(matches!(
+            socket.readln_max(6).await,
+            Err(Error::TooLarge(6))
+        ));
+    }
 }
@@ -0,0 +1,154 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Timetoken units in a second, timetokens count 100 nanoseconds.
+const PER_SECOND: u64 = 10_000_000;
+
+/// Timetoken units in a millisecond.
+const PER_MILLISECOND: u64 = 10_000;
+
+/// Most digits of a timetoken.
+const MAX_DIGITS: usize = 17;
+
+/// The value is not a timetoken.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError(String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid timetoken {:?}", self.0)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// # `PubNub` Timetoken
+///
+/// Publish time of a `PubNub` message in 100 nanosecond units since the
+/// Unix epoch, the 17 digit value `PubNub` returns as a string. Ordered
+/// by time, [`Timetoken::default`] is the `0` timetoken subscriptions
+/// start from.
+///
+/// ```
+/// use kafka_bridge::timetoken::Timetoken;
+///
+/// let timetoken: Timetoken = "17000000001234567".parse().expect("parse");
+/// assert_eq!(timetoken.as_millis(), 1_700_000_000_123);
+/// assert_eq!(timetoken.to_string(), "17000000001234567");
+/// assert!(Timetoken::from_millis(1_700_000_000_124) > timetoken);
+/// ```
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub struct Timetoken(u64);
+
+impl Timetoken {
+    #[must_use]
+    pub fn new(value: u64) -> Self {
+        Self(value)
+    }
+
+    /// Timetoken of the current system time.
+    #[must_use]
+    pub fn now() -> Self {
+        SystemTime::now().into()
+    }
+
+    #[must_use]
+    pub fn from_millis(millis: u64) -> Self {
+        Self(millis.saturating_mul(PER_MILLISECOND))
+    }
+
+    /// Milliseconds since the Unix epoch, such as Kafka record timestamps.
+    #[must_use]
+    pub fn as_millis(self) -> u64 {
+        self.0 / PER_MILLISECOND
+    }
+
+    #[must_use]
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl FromStr for Timetoken {
+    type Err = ParseError;
+
+    /// Parses up to 17 decimal digits.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.is_empty()
+            || value.len() > MAX_DIGITS
+            || !value.bytes().all(|byte| byte.is_ascii_digit())
+        {
+            return Err(ParseError(value.into()));
+        }
+        value
+            .parse()
+            .map(Self)
+            .map_err(|_| ParseError(value.into()))
+    }
+}
+
+impl fmt::Display for Timetoken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Times before the Unix epoch are the `0` timetoken.
+impl From<SystemTime> for Timetoken {
+    fn from(time: SystemTime) -> Self {
+        let elapsed = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        Self(
+            elapsed
+                .as_secs()
+                .saturating_mul(PER_SECOND)
+                .saturating_add(u64::from(elapsed.subsec_nanos() / 100)),
+        )
+    }
+}
+
+impl From<Timetoken> for SystemTime {
+    fn from(timetoken: Timetoken) -> Self {
+        UNIX_EPOCH
+            + Duration::from_secs(timetoken.0 / PER_SECOND)
+            + Duration::from_nanos(timetoken.0 % PER_SECOND * 100)
+    }
+}
+
+#[cfg(test)]
+mod timetoken_tests {
+    use super::Timetoken;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn parse_ok() {
+        let timetoken: Timetoken =
+            "17000000001234567".parse().expect("timetoken");
+        assert_eq!(timetoken.as_u64(), 17_000_000_001_234_567);
+        assert_eq!(timetoken.to_string(), "17000000001234567");
+        assert_eq!("0".parse(), Ok(Timetoken::default()));
+
+        for value in &["", "-1", "1.5", " 1", "170000000012345678"] {
+            assert!(value.parse::<Timetoken>().is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn time_ok() {
+        let time = UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789);
+        let timetoken = Timetoken::from(time);
+        assert_eq!(timetoken, Timetoken::new(17_000_000_001_234_567));
+        assert_eq!(
+            SystemTime::from(timetoken),
+            UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_700)
+        );
+        assert_eq!(timetoken.as_millis(), 1_700_000_000_123);
+        assert_eq!(
+            Timetoken::from_millis(1_700_000_000_123),
+            Timetoken::new(17_000_000_001_230_000)
+        );
+        assert!(Timetoken::now() > timetoken);
+    }
+}
@@ -0,0 +1,255 @@
+use crate::socket::{Error, Socket};
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// # Transport
+///
+/// Byte stream the `PubNub` clients send requests and read responses
+/// through. [`Socket`] is the network transport, [`MockTransport`]
+/// replays scripted responses for offline tests.
+#[async_trait]
+pub trait Transport: Send {
+    /// Writes every byte of `data`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the [`Error`] that failed the write.
+    async fn write_all(&mut self, data: &[u8]) -> Result<(), Error>;
+
+    /// Reads up to and including the next `\n`, failing with
+    /// [`Error::TooLarge`] once `max` bytes arrived without it.
+    ///
+    /// # Errors
+    ///
+    /// Returns the [`Error`] that failed the read.
+    async fn readln(&mut self, max: usize) -> Result<String, Error>;
+
+    /// Reads exactly `bytes` bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns the [`Error`] that failed the read.
+    async fn read_exact(&mut self, bytes: usize) -> Result<Vec<u8>, Error>;
+
+    /// Reads until the peer closes the connection, failing with
+    /// [`Error::TooLarge`] after more than `max` bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns the [`Error`] that failed the read.
+    async fn read_to_end(&mut self, max: usize) -> Result<Vec<u8>, Error>;
+
+    /// Closes the connection, the next use reconnects.
+    async fn disconnect(&mut self);
+}
+
+#[async_trait]
+impl Transport for Socket {
+    async fn write_all(&mut self, data: &[u8]) -> Result<(), Error> {
+        Socket::write_all(self, data).await
+    }
+
+    async fn readln(&mut self, max: usize) -> Result<String, Error> {
+        Socket::readln_max(self, max).await
+    }
+
+    async fn read_exact(&mut self, bytes: usize) -> Result<Vec<u8>, Error> {
+        Socket::read_exact(self, bytes).await
+    }
+
+    async fn read_to_end(&mut self, max: usize) -> Result<Vec<u8>, Error> {
+        Socket::read_to_end(self, max).await
+    }
+
+    async fn disconnect(&mut self) {
+        Socket::disconnect(self).await;
+    }
+}
+
+/// Scripted step of a [`MockTransport`].
+#[derive(Debug)]
+pub enum Step {
+    /// Bytes made available to following reads.
+    Receive(Vec<u8>),
+    /// Error returned by the next read.
+    ReadError(Error),
+    /// Error returned by the next write.
+    WriteError(Error),
+}
+
+/// # Mock Transport
+///
+/// In-memory [`Transport`] replaying a script of responses and failures
+/// in order. Reads fail with [`Error::Eof`] once the script is used up,
+/// or until the next write when a write failure is queued.
+/// Every request written is recorded, including failed writes.
+///
+/// ```
+/// use kafka_bridge::transport::{MockTransport, Transport};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let mut transport = MockTransport::new();
+///     transport.push_response("HTTP/1.1 200 OK\r\n\r\n");
+///     let requests = transport.requests();
+///
+///     transport.write_all(b"GET / HTTP/1.1\r\n\r\n").await.expect("write");
+///     let line = transport.readln(1024).await.expect("line");
+///     assert_eq!(line, "HTTP/1.1 200 OK\r\n");
+///     assert_eq!(requests.lock().expect("requests").len(), 1);
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct MockTransport {
+    script: VecDeque<Step>,
+    buffer: VecDeque<u8>,
+    requests: Arc<Mutex<Vec<String>>>,
+    disconnects: usize,
+}
+
+impl MockTransport {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues `data` to be read.
+    pub fn push_response(&mut self, data: impl AsRef<[u8]>) {
+        self.script.push_back(Step::Receive(data.as_ref().to_vec()));
+    }
+
+    /// Queues a failed read.
+    pub fn push_read_error(&mut self, error: Error) {
+        self.script.push_back(Step::ReadError(error));
+    }
+
+    /// Queues a failed write.
+    pub fn push_write_error(&mut self, error: Error) {
+        self.script.push_back(Step::WriteError(error));
+    }
+
+    /// Shared list of the requests written so far, still readable after
+    /// the transport moved into a client.
+    #[must_use]
+    pub fn requests(&self) -> Arc<Mutex<Vec<String>>> {
+        Arc::clone(&self.requests)
+    }
+
+    /// Number of calls to [`Transport::disconnect`].
+    #[must_use]
+    pub fn disconnects(&self) -> usize {
+        self.disconnects
+    }
+
+    /// Moves the next scripted response into the read buffer, returning
+    /// a scripted read failure instead when one is next.
+    fn receive(&mut self) -> Result<(), Error> {
+        match self.script.front() {
+            Some(Step::Receive(_)) => {
+                if let Some(Step::Receive(data)) = self.script.pop_front() {
+                    self.buffer.extend(data);
+                }
+                Ok(())
+            }
+            Some(Step::ReadError(_)) => match self.script.pop_front() {
+                Some(Step::ReadError(error)) => Err(error),
+                _ => Err(Error::Eof),
+            },
+            Some(Step::WriteError(_)) | None => Err(Error::Eof),
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for MockTransport {
+    async fn write_all(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.requests
+            .lock()
+            .expect("requests lock")
+            .push(String::from_utf8_lossy(data).into_owned());
+        if let Some(Step::WriteError(_)) = self.script.front() {
+            if let Some(Step::WriteError(error)) = self.script.pop_front() {
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    async fn readln(&mut self, max: usize) -> Result<String, Error> {
+        let mut line = Vec::new();
+        loop {
+            if line.len() == max {
+                return Err(Error::TooLarge(max));
+            }
+            match self.buffer.pop_front() {
+                Some(byte) => {
+                    line.push(byte);
+                    if byte == b'\n' {
+                        break;
+                    }
+                }
+                None => match self.receive() {
+                    Ok(()) => {}
+                    Err(Error::Eof) if !line.is_empty() => break,
+                    Err(error) => return Err(error),
+                },
+            }
+        }
+        Ok(String::from_utf8_lossy(&line).into_owned())
+    }
+
+    async fn read_exact(&mut self, bytes: usize) -> Result<Vec<u8>, Error> {
+        while self.buffer.len() < bytes {
+            self.receive()?;
+        }
+        Ok(self.buffer.drain(..bytes).collect())
+    }
+
+    /// Reads the rest of the current scripted response, standing in for
+    /// a connection closed by the peer.
+    async fn read_to_end(&mut self, max: usize) -> Result<Vec<u8>, Error> {
+        if self.buffer.is_empty() {
+            match self.receive() {
+                Ok(()) | Err(Error::Eof) => {}
+                Err(error) => return Err(error),
+            }
+        }
+        if self.buffer.len() > max {
+            return Err(Error::TooLarge(max));
+        }
+        Ok(self.buffer.drain(..).collect())
+    }
+
+    async fn disconnect(&mut self) {
+        self.buffer.clear();
+        self.disconnects += 1;
+    }
+}
+
+#[cfg(test)]
+mod transport_tests {
+    use super::{MockTransport, Transport};
+    use crate::socket::Error;
+
+    #[tokio::test]
+    async fn mock_script_ok() {
+        let mut transport = MockTransport::new();
+        transport.push_response("first\nsec");
+        transport.push_response("ond\nbytes");
+        transport.push_read_error(Error::Timeout);
+        transport.push_write_error(Error::Eof);
+
+        assert_eq!(transport.readln(10).await.expect("line"), "first\n");
+        assert!(matches!(transport.readln(3).await, Err(Error::TooLarge(3))));
+        assert_eq!(transport.readln(10).await.expect("line"), "ond\n");
+        assert_eq!(transport.read_exact(5).await.expect("bytes"), b"bytes");
+        assert!(matches!(transport.readln(10).await, Err(Error::Timeout)));
+        assert!(matches!(transport.readln(10).await, Err(Error::Eof)));
+        assert!(matches!(transport.write_all(b"x").await, Err(Error::Eof)));
+        assert!(transport.write_all(b"y").await.is_ok());
+
+        let requests = transport.requests();
+        assert_eq!(*requests.lock().expect("requests"), vec!["x", "y"]);
+    }
+}